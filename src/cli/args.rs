@@ -0,0 +1,203 @@
+use crate::core::constants::VERSION;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+#[derive(Parser)]
+#[command(
+    name = "deploy",
+    version = VERSION,
+    about = "simple deployment tool for debian based target servers"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Upload changed files to targets and run deploy actions
+    Deploy(DeployArgs),
+    /// Show files that would be uploaded and actions that would run
+    Plan(PlanArgs),
+    /// Check config without connecting to targets
+    Validate(ConfigArgs),
+    /// Run a command on targets
+    Exec(ExecArgs),
+    /// Restore previous state of a package on targets
+    Rollback(RollbackArgs),
+    /// Check connectivity and deployed packages on targets
+    Status(StatusArgs),
+}
+
+#[derive(Args)]
+pub struct ConfigArgs {
+    /// Path to deploy configuration
+    #[arg(short, long, value_name = "FILE")]
+    pub config: String,
+}
+
+#[derive(Args)]
+pub struct SelectArgs {
+    /// Select targets by tags, groups and names, e.g. `web,!canary`
+    #[arg(short, long, value_name = "EXPR")]
+    pub select: Option<String>,
+    /// Only use targets matching name or glob, may be repeated
+    #[arg(short, long = "target", value_name = "NAME")]
+    pub targets: Vec<String>,
+    /// Only use packages with this name, may be repeated
+    #[arg(short, long = "package", value_name = "NAME")]
+    pub packages: Vec<String>,
+}
+
+#[derive(Args)]
+pub struct DeployArgs {
+    #[command(flatten)]
+    pub config: ConfigArgs,
+    #[command(flatten)]
+    pub select: SelectArgs,
+    /// Close ui once deployment is finished
+    #[arg(long)]
+    pub exit_on_finish: bool,
+    /// Show what would change instead of deploying
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Hash files on targets instead of trusting manifest of the last deploy
+    #[arg(long)]
+    pub verify: bool,
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub output: OutputFormat,
+}
+
+#[derive(Args)]
+pub struct PlanArgs {
+    #[command(flatten)]
+    pub config: ConfigArgs,
+    #[command(flatten)]
+    pub select: SelectArgs,
+    /// Hash files on targets instead of trusting manifest of the last deploy
+    #[arg(long)]
+    pub verify: bool,
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub output: OutputFormat,
+}
+
+#[derive(Args)]
+pub struct ExecArgs {
+    #[command(flatten)]
+    pub config: ConfigArgs,
+    #[command(flatten)]
+    pub select: SelectArgs,
+    /// Don't ask for confirmation
+    #[arg(short, long)]
+    pub yes: bool,
+    /// Command to run on each target
+    #[arg(required = true, trailing_var_arg = true)]
+    pub command: Vec<String>,
+}
+
+#[derive(Args)]
+pub struct RollbackArgs {
+    #[command(flatten)]
+    pub config: ConfigArgs,
+    #[command(flatten)]
+    pub select: SelectArgs,
+    /// Deploy (release or backup timestamp) whose state to restore, later deploys are undone,
+    /// the one before the latest by default
+    #[arg(long, value_name = "DEPLOY_ID")]
+    pub to: Option<String>,
+    /// Don't ask for confirmation
+    #[arg(short, long)]
+    pub yes: bool,
+}
+
+#[derive(Args)]
+pub struct StatusArgs {
+    #[command(flatten)]
+    pub config: ConfigArgs,
+    #[command(flatten)]
+    pub select: SelectArgs,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// Keeps the positional forms working: `deploy <config> [exit_on_finish]` is treated as
+/// `deploy deploy --config <config> [--exit-on-finish]` and `deploy plan <config>`
+/// as `deploy plan --config <config>`.
+pub fn normalize_args(mut args: Vec<String>) -> Vec<String> {
+    let commands = [
+        "deploy", "plan", "validate", "exec", "rollback", "status", "help",
+    ];
+    let config_commands = ["deploy", "plan", "validate", "status"];
+
+    if args.len() > 1 && !args[1].starts_with('-') && !commands.contains(&args[1].as_str()) {
+        args.insert(1, "deploy".to_string());
+        if args.len() > 3 && !args[3].starts_with('-') {
+            let exit_on_finish = args.remove(3);
+            if exit_on_finish.parse().unwrap_or(false) {
+                args.push("--exit-on-finish".to_string());
+            }
+        }
+    }
+
+    if args.len() > 2 && config_commands.contains(&args[1].as_str()) && !args[2].starts_with('-') {
+        args.insert(2, "--config".to_string());
+    }
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::normalize_args;
+
+    fn normalize(args: &str) -> String {
+        normalize_args(args.split(' ').map(String::from).collect()).join(" ")
+    }
+
+    #[test]
+    fn legacy_config_is_deployed() {
+        assert_eq!(
+            normalize("deploy cfg.json"),
+            "deploy deploy --config cfg.json"
+        );
+    }
+
+    #[test]
+    fn legacy_exit_on_finish() {
+        assert_eq!(
+            normalize("deploy cfg.json true"),
+            "deploy deploy --config cfg.json --exit-on-finish"
+        );
+        assert_eq!(
+            normalize("deploy cfg.json false"),
+            "deploy deploy --config cfg.json"
+        );
+    }
+
+    #[test]
+    fn positional_config_of_subcommand() {
+        assert_eq!(
+            normalize("deploy plan cfg.json"),
+            "deploy plan --config cfg.json"
+        );
+        assert_eq!(
+            normalize("deploy status cfg.json"),
+            "deploy status --config cfg.json"
+        );
+    }
+
+    #[test]
+    fn flags_are_kept() {
+        assert_eq!(
+            normalize("deploy deploy --config cfg.json --exit-on-finish"),
+            "deploy deploy --config cfg.json --exit-on-finish"
+        );
+        assert_eq!(
+            normalize("deploy exec --config cfg.json -- uptime"),
+            "deploy exec --config cfg.json -- uptime"
+        );
+        assert_eq!(normalize("deploy --help"), "deploy --help");
+    }
+}
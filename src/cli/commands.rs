@@ -0,0 +1,368 @@
+use super::{
+    args::{OutputFormat, SelectArgs},
+    selection::apply_selection,
+};
+use crate::{
+    core::constants::{EXIT_CONFIG_ERROR, EXIT_FAILURE, EXIT_SUCCESS},
+    deployment::{
+        deployment::{begin_plan, open_session},
+        packaging::FileChangeKind,
+        remote::{run_command, shell_quote},
+        rollback::rollback,
+        summary::{DeploymentSummary, TargetResult},
+    },
+    serialization::config::Config,
+    states::ui_state::UIStore,
+};
+use futures::{future::join_all, lock::Mutex};
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::sync::Arc;
+
+/// Reads config and narrows it down to selected targets and packages,
+/// `local_files` is set for commands packing local files.
+pub fn load_config(path: &str, select: &SelectArgs, local_files: bool) -> Result<Config, String> {
+    let mut config = read_config(path, local_files)?;
+    apply_selection(&mut config, select)?;
+    Ok(config)
+}
+
+/// Reads and validates config, returns printable error on failure.
+pub fn read_config(path: &str, local_files: bool) -> Result<Config, String> {
+    if !std::path::Path::new(path).exists() {
+        return Err(format!("Config {} doesn't exists", path));
+    }
+
+    let config =
+        Config::read_config(path).map_err(|e| format!("Can't read config {}: {}", path, e))?;
+
+    let problems = config.validate(local_files);
+    if !problems.is_empty() {
+        return Err(format!(
+            "Config {} has errors:\n  {}",
+            path,
+            problems.join("\n  ")
+        ));
+    }
+
+    Ok(config)
+}
+
+/// Asks user to confirm an action, refuses when stdin is not interactive.
+pub fn confirm(question: &str, yes: bool) -> bool {
+    if yes {
+        return true;
+    }
+    if !io::stdin().is_terminal() {
+        println!(
+            "Error! {} Use --yes to confirm in non-interactive mode.",
+            question
+        );
+        return false;
+    }
+
+    print!("{} [y/N] ", question);
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim(), "y" | "Y" | "yes")
+}
+
+pub fn run_validate(config_path: &str) -> u8 {
+    match read_config(config_path, true) {
+        Ok(config) => {
+            println!(
+                "Config {} is valid, {} targets, {} packages.",
+                config_path,
+                config.targets.len(),
+                config.packages.len()
+            );
+            EXIT_SUCCESS
+        }
+        Err(e) => {
+            println!("Error! {}", e);
+            EXIT_CONFIG_ERROR
+        }
+    }
+}
+
+pub async fn run_plan(
+    config: Arc<Mutex<Config>>,
+    ui_state: Arc<Mutex<UIStore>>,
+    output: OutputFormat,
+) -> u8 {
+    let plans = match begin_plan(config, ui_state).await {
+        Ok(plans) => plans,
+        Err(e) => {
+            eprintln!("Plan failed: {:#}", e);
+            return EXIT_FAILURE;
+        }
+    };
+
+    for target in &plans {
+        if output == OutputFormat::Json {
+            if let Ok(line) = serde_json::to_string(target) {
+                println!("{}", line);
+            }
+            continue;
+        }
+
+        println!("{} ({})", target.result.name, target.result.host);
+        if let Some(e) = &target.result.error {
+            println!("  failed: {}", e);
+            continue;
+        }
+
+        for package in &target.packages {
+            if package.is_empty() {
+                println!("  {}: no changes", package.package);
+                continue;
+            }
+
+            let deleted = match package.deletions.len() {
+                0 => String::new(),
+                count => format!(", {} deleted", count),
+            };
+            println!(
+                "  {} -> {} ({} files, {}{})",
+                package.package,
+                package.target_directory,
+                package.changes.len(),
+                format_size(package.total_size()),
+                deleted
+            );
+            for change in &package.changes {
+                let kind = match change.kind {
+                    FileChangeKind::New => "new",
+                    FileChangeKind::Modified => "modified",
+                };
+                println!(
+                    "    {:<9}{:>11}  {}",
+                    kind,
+                    format_size(change.size),
+                    change.path
+                );
+            }
+            for path in &package.deletions {
+                println!("    {:<9}{:>11}  {}", "deleted", "", path);
+            }
+            for action in &package.pre_deploy_actions {
+                println!("    pre-deploy:  {}", action);
+            }
+            for action in &package.post_deploy_actions {
+                println!("    post-deploy: {}", action);
+            }
+        }
+    }
+
+    let summary = DeploymentSummary {
+        targets: plans.into_iter().map(|plan| plan.result).collect(),
+    };
+    summary.exit_code()
+}
+
+pub async fn run_exec(config: Config, args: &[String], yes: bool) -> u8 {
+    // arguments are quoted, so `sh -c "echo a b"` reaches target as a single script
+    let command = args
+        .iter()
+        .map(|arg| shell_quote(arg))
+        .collect::<Vec<String>>()
+        .join(" ");
+    let question = format!("Run `{}` on {} targets?", command, config.targets.len());
+    if !confirm(&question, yes) {
+        return EXIT_FAILURE;
+    }
+
+    let config = Arc::new(config);
+    let mut tasks = Vec::new();
+    for target in config.targets.clone() {
+        let config = config.clone();
+        let command = command.to_string();
+        tasks.push(tokio::spawn(async move {
+            let session = open_session(&config, &target).await?;
+            run_command(&session, &command).await
+        }));
+    }
+
+    let results = join_all(tasks).await;
+
+    let mut summary = DeploymentSummary {
+        targets: Vec::new(),
+    };
+    for (target, res) in config.targets.iter().zip(results) {
+        let name = target.name.to_owned().unwrap_or(String::from("unnamed"));
+        let host = target.host.to_string();
+        println!("== {} ({}) ==", name, host);
+
+        let res = match res {
+            Ok(res) => res,
+            Err(e) => Err(anyhow::anyhow!("exec task crashed: {}", e)),
+        };
+        let target_result = match res {
+            Ok(output) => {
+                print!("{}", output.stdout);
+                eprint!("{}", output.stderr);
+                if output.success() {
+                    TargetResult::succeeded(name, host)
+                } else {
+                    let err = anyhow::anyhow!(
+                        "command exited with code {}",
+                        output
+                            .exit_status
+                            .map(|status| status.to_string())
+                            .unwrap_or(String::from("unknown"))
+                    );
+                    println!("{}", err);
+                    TargetResult::failed(name, host, &err)
+                }
+            }
+            Err(e) => {
+                println!("failed: {:#}", e);
+                TargetResult::failed(name, host, &e)
+            }
+        };
+        summary.targets.push(target_result);
+    }
+
+    summary.exit_code()
+}
+
+pub async fn run_status(config: Config) -> u8 {
+    let config = Arc::new(config);
+    let mut tasks = Vec::new();
+    for target in config.targets.clone() {
+        let config = config.clone();
+        tasks.push(tokio::spawn(async move {
+            let session = open_session(&config, &target).await?;
+            let mut packages: Vec<(String, bool)> = Vec::new();
+            for package in &target.packages {
+                let Some(package_element) = config.packages.get(package) else {
+                    continue;
+                };
+                let fmt = format!("test -d {}", shell_quote(&package_element.live_directory()));
+                let output = run_command(&session, &fmt).await?;
+                packages.push((package.to_string(), output.success()));
+            }
+            anyhow::Ok(packages)
+        }));
+    }
+
+    let results = join_all(tasks).await;
+
+    let mut summary = DeploymentSummary {
+        targets: Vec::new(),
+    };
+    for (target, res) in config.targets.iter().zip(results) {
+        let name = target.name.to_owned().unwrap_or(String::from("unnamed"));
+        let host = target.host.to_string();
+        let res = match res {
+            Ok(res) => res,
+            Err(e) => Err(anyhow::anyhow!("status task crashed: {}", e)),
+        };
+        match res {
+            Ok(packages) => {
+                println!("{} ({}): reachable", name, host);
+                for (package, deployed) in packages {
+                    let state = if deployed { "deployed" } else { "not deployed" };
+                    println!("  {}: {}", package, state);
+                }
+                summary.targets.push(TargetResult::succeeded(name, host));
+            }
+            Err(e) => {
+                println!("{} ({}): failed: {:#}", name, host, e);
+                summary.targets.push(TargetResult::failed(name, host, &e));
+            }
+        }
+    }
+
+    summary.exit_code()
+}
+
+pub async fn run_rollback(config: Config, to: Option<String>, yes: bool) -> u8 {
+    let targets: Vec<String> = config
+        .targets
+        .iter()
+        .map(|target| {
+            format!(
+                "{} ({})",
+                target.name.to_owned().unwrap_or(String::from("unnamed")),
+                target.packages.join(", ")
+            )
+        })
+        .collect();
+    let question = format!("Roll back {}?", targets.join(", "));
+    if !confirm(&question, yes) {
+        return EXIT_FAILURE;
+    }
+
+    let config = Arc::new(config);
+    let mut tasks = Vec::new();
+    for target in config.targets.clone() {
+        let config = config.clone();
+        let to = to.clone();
+        tasks.push(tokio::spawn(async move {
+            rollback(&config, &target, to.as_deref()).await
+        }));
+    }
+
+    let results = join_all(tasks).await;
+
+    let mut summary = DeploymentSummary {
+        targets: Vec::new(),
+    };
+    for (target, res) in config.targets.iter().zip(results) {
+        let name = target.name.to_owned().unwrap_or(String::from("unnamed"));
+        let host = target.host.to_string();
+        let res = match res {
+            Ok(res) => res,
+            Err(e) => Err(anyhow::anyhow!("rollback task crashed: {}", e)),
+        };
+        match res {
+            Ok(restored) => {
+                for (package, release) in restored {
+                    println!(
+                        "{} ({}): {} rolled back to {}",
+                        name, host, package, release
+                    );
+                }
+                summary.targets.push(TargetResult::succeeded(name, host));
+            }
+            Err(e) => {
+                println!("{} ({}): rollback failed: {:#}", name, host, e);
+                summary.targets.push(TargetResult::failed(name, host, &e));
+            }
+        }
+    }
+
+    summary.exit_code()
+}
+
+pub fn print_summary(summary: &DeploymentSummary) {
+    for target in &summary.targets {
+        match &target.error {
+            None => println!("{} ({}): succeeded", target.name, target.host),
+            Some(e) => println!("{} ({}): failed: {}", target.name, target.host, e),
+        }
+    }
+    println!(
+        "Deployment complete, {} succeeded, {} failed.",
+        summary.succeeded_count(),
+        summary.failed_count()
+    );
+}
+
+pub fn format_size(size: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", size, units[unit])
+    } else {
+        format!("{:.1} {}", value, units[unit])
+    }
+}
@@ -0,0 +1,209 @@
+use super::args::SelectArgs;
+use crate::serialization::{config::Config, deploy_target::DeployTarget};
+use globset::{Glob, GlobMatcher};
+use std::collections::HashMap;
+
+/// Removes targets and packages which were not selected from command line,
+/// targets left without packages are removed as well.
+pub fn apply_selection(config: &mut Config, select: &SelectArgs) -> Result<(), String> {
+    if let Some(expression) = &select.select {
+        select_expression(config, expression)?;
+    }
+
+    if !select.targets.is_empty() {
+        let mut matchers: Vec<(&String, GlobMatcher, bool)> = Vec::new();
+        for pattern in &select.targets {
+            let glob = Glob::new(pattern)
+                .map_err(|e| format!("invalid target pattern {}: {}", pattern, e))?;
+            matchers.push((pattern, glob.compile_matcher(), false));
+        }
+
+        config.targets.retain(|target| {
+            let mut selected = false;
+            for (_, matcher, used) in matchers.iter_mut() {
+                let name_match = target
+                    .name
+                    .as_ref()
+                    .is_some_and(|name| matcher.is_match(name));
+                if name_match || matcher.is_match(&target.host) {
+                    *used = true;
+                    selected = true;
+                }
+            }
+            selected
+        });
+
+        if let Some((pattern, _, _)) = matchers.iter().find(|(_, _, used)| !used) {
+            return Err(format!("no targets match {}", pattern));
+        }
+    }
+
+    if !select.packages.is_empty() {
+        for package in &select.packages {
+            if !config.packages.contains_key(package) {
+                return Err(format!("package {} is not defined", package));
+            }
+        }
+
+        for target in config.targets.iter_mut() {
+            target
+                .packages
+                .retain(|package| select.packages.contains(package));
+        }
+        config.targets.retain(|target| !target.packages.is_empty());
+
+        if config.targets.is_empty() {
+            return Err(format!(
+                "no selected targets use package {}",
+                select.packages.join(", ")
+            ));
+        }
+    }
+
+    // only packages which are still deployed somewhere are counted
+    config
+        .packages
+        .retain(|name, _| config.targets.iter().any(|t| t.packages.contains(name)));
+
+    Ok(())
+}
+
+/// Applies selection expression: comma separated tags, groups or target names (globs allowed),
+/// `&term` keeps only targets also matching term and `!term` removes matching targets.
+fn select_expression(config: &mut Config, expression: &str) -> Result<(), String> {
+    let groups = config.groups.clone().unwrap_or_default();
+    let mut include: Vec<&str> = Vec::new();
+    let mut intersect: Vec<&str> = Vec::new();
+    let mut exclude: Vec<&str> = Vec::new();
+
+    for term in expression
+        .split(',')
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+    {
+        let (list, name) = if let Some(name) = term.strip_prefix('!') {
+            (&mut exclude, name)
+        } else if let Some(name) = term.strip_prefix('&') {
+            (&mut intersect, name)
+        } else {
+            (&mut include, term)
+        };
+
+        Glob::new(name).map_err(|e| format!("invalid selection term {}: {}", name, e))?;
+        if !config
+            .targets
+            .iter()
+            .any(|target| term_matches(&groups, name, target, &mut Vec::new()))
+        {
+            return Err(format!("{} is not a target, tag or group", name));
+        }
+        list.push(name);
+    }
+
+    config.targets.retain(|target| {
+        let mut matches = |name: &&str| term_matches(&groups, name, target, &mut Vec::new());
+        (include.is_empty() || include.iter().any(&mut matches))
+            && intersect.iter().all(&mut matches)
+            && !exclude.iter().any(&mut matches)
+    });
+
+    if config.targets.is_empty() {
+        return Err(format!("no targets match {}", expression));
+    }
+    Ok(())
+}
+
+fn term_matches<'a>(
+    groups: &'a HashMap<String, Vec<String>>,
+    term: &'a str,
+    target: &DeployTarget,
+    visited: &mut Vec<&'a str>,
+) -> bool {
+    if let Some(members) = groups.get(term) {
+        // groups may include each other, skip already visited ones
+        if visited.contains(&term) {
+            return false;
+        }
+        visited.push(term);
+        return members
+            .iter()
+            .any(|member| term_matches(groups, member, target, visited));
+    }
+
+    if target.matches_name_or_tag(term) {
+        return true;
+    }
+
+    let Ok(glob) = Glob::new(term) else {
+        return false;
+    };
+    let matcher = glob.compile_matcher();
+    target
+        .name
+        .as_ref()
+        .is_some_and(|name| matcher.is_match(name))
+        || matcher.is_match(&target.host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::select_expression;
+    use crate::serialization::config::Config;
+
+    fn config() -> Config {
+        serde_json::from_str(
+            r#"{
+                "targets": [
+                    {"name": "web1", "host": "10.0.0.1", "port": 22, "authentication": {"password": "u:p"},
+                     "packages": ["app"], "tags": ["web", "canary"]},
+                    {"name": "web2", "host": "10.0.0.2", "port": 22, "authentication": {"password": "u:p"},
+                     "packages": ["app"], "tags": ["web"]},
+                    {"name": "db1", "host": "10.0.0.3", "port": 22, "authentication": {"password": "u:p"},
+                     "packages": ["app"], "tags": ["db"]}
+                ],
+                "groups": {
+                    "production": ["web", "db"],
+                    "loop-a": ["loop-b"],
+                    "loop-b": ["loop-a", "db1"]
+                },
+                "packages": {"app": {"localDirectory": "./", "targetDirectory": "/srv/app/"}}
+            }"#,
+        )
+        .unwrap()
+    }
+
+    fn select(expression: &str) -> Result<Vec<String>, String> {
+        let mut config = config();
+        select_expression(&mut config, expression)?;
+        Ok(config
+            .targets
+            .iter()
+            .map(|target| target.name.clone().unwrap_or_default())
+            .collect())
+    }
+
+    #[test]
+    fn includes_tags_names_and_globs() {
+        assert_eq!(select("web").unwrap(), ["web1", "web2"]);
+        assert_eq!(select("db1,canary").unwrap(), ["web1", "db1"]);
+        assert_eq!(select("web*").unwrap(), ["web1", "web2"]);
+    }
+
+    #[test]
+    fn intersects_and_excludes() {
+        assert_eq!(select("web,&canary").unwrap(), ["web1"]);
+        assert_eq!(select("production,!canary").unwrap(), ["web2", "db1"]);
+        assert_eq!(select("!db").unwrap(), ["web1", "web2"]);
+    }
+
+    #[test]
+    fn group_cycles_terminate() {
+        assert_eq!(select("loop-a").unwrap(), ["db1"]);
+    }
+
+    #[test]
+    fn rejects_unknown_terms_and_empty_result() {
+        assert!(select("staging").is_err());
+        assert!(select("canary,&db").is_err());
+    }
+}
@@ -2,3 +2,20 @@ pub static VERSION: &str = env!("CARGO_PKG_VERSION");
 pub static SUDO_PREPEND: &str = "sudo ";
 pub static CHUNK_UPLOAD_RETRIES: u32 = 5;
 pub static CHUNK_UPLOAD_BUFFER: usize = 40_960;
+pub static RETRY_MAX_ATTEMPTS: u32 = 5;
+pub static RETRY_INITIAL_DELAY_MS: u64 = 1_000;
+pub static RETRY_BACKOFF_FACTOR: f64 = 2.0;
+pub static RETRY_MAX_DELAY_MS: u64 = 30_000;
+pub static RETRY_JITTER: f64 = 0.2;
+pub static EXIT_SUCCESS: u8 = 0;
+pub static EXIT_FAILURE: u8 = 1;
+pub static EXIT_CONFIG_ERROR: u8 = 2;
+pub static EXIT_PARTIAL_FAILURE: u8 = 3;
+pub static EXIT_AUTH_FAILURE: u8 = 4;
+pub static EXIT_CANCELLED: u8 = 130;
+pub static HEALTH_CHECK_TIMEOUT_SECONDS: u64 = 10;
+pub static HEALTH_CHECK_RETRIES: u32 = 3;
+pub static HEALTH_CHECK_INTERVAL_SECONDS: u64 = 5;
+pub static RELEASES_KEEP: usize = 5;
+pub static BACKUPS_KEEP: usize = 5;
+pub static STATE_DIRECTORY: &str = "/var/lib/deploy-rs";
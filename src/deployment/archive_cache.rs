@@ -0,0 +1,79 @@
+use super::packaging::PackageCreator;
+use crate::serialization::compression::Compression;
+use futures::lock::Mutex;
+use sha1::{Digest, Sha1};
+use std::{collections::HashMap, fs::File, io, path::PathBuf, sync::Arc};
+use tempfile::TempDir;
+
+/// Package archives built during the run, keyed by the files they contain,
+/// so targets needing the same changes share one archive.
+pub struct ArchiveCache {
+    dir: TempDir,
+    archives: Mutex<HashMap<String, Arc<Mutex<Option<PathBuf>>>>>,
+}
+
+impl ArchiveCache {
+    pub fn new() -> io::Result<ArchiveCache> {
+        Ok(ArchiveCache {
+            dir: tempfile::tempdir()?,
+            archives: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Returns archive with `files` (path and hash) of local directory,
+    /// building it on blocking pool when no target requested it before.
+    pub async fn archive(
+        &self,
+        local_dir: &str,
+        files: &[(String, String)],
+        compression: Compression,
+    ) -> anyhow::Result<PathBuf, anyhow::Error> {
+        let key = archive_key(local_dir, files, &compression);
+
+        // only the entry is locked while building, other archives are built concurrently
+        let entry = {
+            let mut archives = self.archives.lock().await;
+            archives.entry(key.to_string()).or_default().clone()
+        };
+        let mut entry = entry.lock().await;
+        if let Some(path) = entry.as_ref() {
+            return Ok(path.clone());
+        }
+
+        let path = self
+            .dir
+            .path()
+            .join(format!("{}.{}", key, compression.extension()));
+        let archive_path = path.clone();
+        let local_dir = local_dir.to_string();
+        let files: Vec<String> = files.iter().map(|(file, _)| file.to_string()).collect();
+        tokio::task::spawn_blocking(move || {
+            let local_temp_file = File::create(&archive_path)?;
+            PackageCreator::build_archive(&local_temp_file, &local_dir, &files, &compression)
+        })
+        .await??;
+
+        *entry = Some(path.clone());
+        Ok(path)
+    }
+}
+
+fn archive_key(local_dir: &str, files: &[(String, String)], compression: &Compression) -> String {
+    let mut sorted: Vec<&(String, String)> = files.iter().collect();
+    sorted.sort();
+
+    let mut hasher = Sha1::new();
+    hasher.update(local_dir.as_bytes());
+    hasher.update(format!("\0{}:{}", compression.codec, compression.level()).as_bytes());
+    for (file, hash) in sorted {
+        hasher.update(b"\0");
+        hasher.update(file.as_bytes());
+        hasher.update(b"\0");
+        hasher.update(hash.as_bytes());
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
@@ -0,0 +1,161 @@
+use crate::serialization::{
+    config::Config, deploy_target::DeployTarget, host_key_policy::HostKeyPolicy,
+};
+use russh::client;
+use russh::keys::{known_hosts, ssh_key::Fingerprint, HashAlg, PublicKey};
+use std::{fmt, path::PathBuf, str::FromStr};
+
+#[derive(Debug)]
+pub enum ClientError {
+    Ssh(russh::Error),
+    HostKeyRejected(String),
+    AuthenticationFailed(String),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Ssh(e) => write!(f, "{}", e),
+            ClientError::HostKeyRejected(reason) => write!(f, "host key rejected: {}", reason),
+            ClientError::AuthenticationFailed(user) => {
+                write!(f, "authentication failed for {}", user)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<russh::Error> for ClientError {
+    fn from(e: russh::Error) -> Self {
+        ClientError::Ssh(e)
+    }
+}
+
+#[derive(Clone)]
+pub struct HostKeyVerifier {
+    host: String,
+    port: u16,
+    policy: HostKeyPolicy,
+    known_hosts_file: Option<PathBuf>,
+    host_key: Option<String>,
+    host_key_fingerprint: Option<String>,
+}
+
+impl HostKeyVerifier {
+    pub fn new(config: &Config, target: &DeployTarget) -> HostKeyVerifier {
+        HostKeyVerifier {
+            host: target.host.to_string(),
+            port: target.port,
+            policy: config.host_key_policy.unwrap_or_default(),
+            known_hosts_file: config.known_hosts_file.as_ref().map(PathBuf::from),
+            host_key: target.host_key.to_owned(),
+            host_key_fingerprint: target.host_key_fingerprint.to_owned(),
+        }
+    }
+
+    pub fn verify(&self, key: &PublicKey) -> Result<(), String> {
+        // keys pinned in target config take precedence over known_hosts
+        if self.host_key.is_some() || self.host_key_fingerprint.is_some() {
+            return self.verify_pinned(key);
+        }
+
+        if self.policy == HostKeyPolicy::Off {
+            return Ok(());
+        }
+
+        let check = match &self.known_hosts_file {
+            Some(path) => known_hosts::check_known_hosts_path(&self.host, self.port, key, path),
+            None => known_hosts::check_known_hosts(&self.host, self.port, key),
+        };
+
+        match check {
+            Ok(true) => Ok(()),
+            Ok(false) if self.policy == HostKeyPolicy::AcceptNew => {
+                // like OpenSSH, only hosts without any known key are learned,
+                // a known host offering a key of another type is rejected
+                let known_keys = match &self.known_hosts_file {
+                    Some(path) => known_hosts::known_host_keys_path(&self.host, self.port, path),
+                    None => known_hosts::known_host_keys(&self.host, self.port),
+                }
+                .map_err(|e| format!("can't read known_hosts: {}", e))?;
+                if let Some((line, _)) = known_keys.first() {
+                    return Err(format!(
+                        "{} ({}) is not present in known_hosts, host is known with another key at line {}",
+                        self.host,
+                        key.fingerprint(HashAlg::Sha256),
+                        line
+                    ));
+                }
+
+                // trust on first use, failing to remember the key is not fatal
+                let _ = match &self.known_hosts_file {
+                    Some(path) => {
+                        known_hosts::learn_known_hosts_path(&self.host, self.port, key, path)
+                    }
+                    None => known_hosts::learn_known_hosts(&self.host, self.port, key),
+                };
+                Ok(())
+            }
+            Ok(false) => Err(format!(
+                "{} ({}) is not present in known_hosts",
+                self.host,
+                key.fingerprint(HashAlg::Sha256)
+            )),
+            Err(russh::keys::Error::KeyChanged { line }) => Err(format!(
+                "{} ({}) doesn't match known_hosts entry at line {}",
+                self.host,
+                key.fingerprint(HashAlg::Sha256),
+                line
+            )),
+            Err(e) => Err(format!("can't read known_hosts: {}", e)),
+        }
+    }
+
+    fn verify_pinned(&self, key: &PublicKey) -> Result<(), String> {
+        if let Some(host_key) = &self.host_key {
+            let expected = PublicKey::from_openssh(host_key)
+                .or_else(|_| russh::keys::parse_public_key_base64(host_key))
+                .map_err(|e| format!("invalid hostKey in config: {}", e))?;
+            if expected.key_data() != key.key_data() {
+                return Err(format!(
+                    "{} ({}) doesn't match configured hostKey",
+                    self.host,
+                    key.fingerprint(HashAlg::Sha256)
+                ));
+            }
+        }
+
+        if let Some(host_key_fingerprint) = &self.host_key_fingerprint {
+            let expected = Fingerprint::from_str(host_key_fingerprint)
+                .map_err(|e| format!("invalid hostKeyFingerprint in config: {}", e))?;
+            let actual = key.fingerprint(expected.algorithm());
+            if expected != actual {
+                return Err(format!(
+                    "{} ({}) doesn't match configured hostKeyFingerprint {}",
+                    self.host, actual, expected
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub struct Client {
+    pub verifier: HostKeyVerifier,
+}
+
+impl client::Handler for Client {
+    type Error = ClientError;
+
+    async fn check_server_key(
+        &mut self,
+        server_public_key: &russh::keys::PublicKey,
+    ) -> Result<bool, Self::Error> {
+        match self.verifier.verify(server_public_key) {
+            Ok(()) => Ok(true),
+            Err(reason) => Err(ClientError::HostKeyRejected(reason)),
+        }
+    }
+}
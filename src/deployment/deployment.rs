@@ -1,6 +1,28 @@
-use super::packaging::PackageCreator;
-use crate::core::constants::{CHUNK_UPLOAD_BUFFER, CHUNK_UPLOAD_RETRIES, SUDO_PREPEND};
+use super::archive_cache::ArchiveCache;
+use super::client::{Client, ClientError, HostKeyVerifier};
+use super::hash_cache::{FileHash, HashCache, HashProgress};
+use super::health_check::run_health_check;
+use super::manifest::{flag_manifest_stale, open_sftp, read_manifest, write_manifest, Manifest};
+use super::packaging::{FileChange, FileChangeKind, PackageCreator};
+use super::plan::{PackagePlan, TargetPlan};
+use super::remote::{
+    parse_sha1sum_output, remote_sha1, run_action, run_command, run_named_action, shell_quote,
+    ActionError,
+};
+use super::retry::Retry;
+use super::summary::{DeploymentSummary, TargetOutcome, TargetResult};
+use crate::core::constants::{
+    BACKUPS_KEEP, CHUNK_UPLOAD_BUFFER, CHUNK_UPLOAD_RETRIES, SUDO_PREPEND,
+};
+use crate::serialization::canary::Canary;
+use crate::serialization::compression::{Compression, CompressionCodec};
+use crate::serialization::deploy_action::DeployAction;
 use crate::serialization::deploy_package::DeployPackage;
+use crate::serialization::package_filter::PackageFilter;
+use crate::serialization::releases::Releases;
+use crate::serialization::retry_policy::RetryPolicy;
+use crate::serialization::rollout_strategy::RolloutStrategy;
+use crate::states::deploy_event::DeployEvent;
 use crate::states::ui_state::{TargetState, UIScreen, UITargetState};
 use crate::{
     serialization::{config::Config, deploy_target::DeployTarget},
@@ -9,80 +31,145 @@ use crate::{
 use anyhow::anyhow;
 use futures::future::join_all;
 use futures::lock::Mutex;
-use futures::StreamExt;
+use futures::{stream, StreamExt};
 use russh::client::AuthResult;
+use russh::client::Handle;
 use russh::keys::PrivateKeyWithHashAlg;
-use russh::{client::Handle, *};
 use russh_sftp::client::SftpSession;
+use russh_sftp::protocol::OpenFlags;
+use sha1::{Digest, Sha1};
 use std::cmp::min;
-use std::fs::File;
-use std::{collections::HashMap, sync::Arc};
-use tokio::io::AsyncWriteExt;
-//use tokio::time::{sleep, Duration};
+use std::io::SeekFrom;
+use std::path::Path;
+use std::time::Duration;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{atomic::Ordering, Arc},
+};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio::time::sleep;
 
 const CMD_FILES_LIMIT: u16 = 512;
 
-pub struct Client {}
-
-impl client::Handler for Client {
-    type Error = russh::Error;
-
-    async fn check_server_key(
-        &mut self,
-        _server_public_key: &russh::keys::PublicKey,
-    ) -> Result<bool, Self::Error> {
-        Ok(true)
-    }
-}
-
 pub async fn begin_deployment(
     config: Arc<Mutex<Config>>,
     ui_state: Arc<Mutex<UIStore>>,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<DeploymentSummary> {
     // 1. loop through targets
-    let mut copyied_deploy_targets: Vec<DeployTarget> = Vec::new();
+    let copyied_deploy_targets: Vec<DeployTarget>;
+    let max_parallel: usize;
+    let rollout: RolloutStrategy;
+    let canary: Canary;
     {
         let config_res = config.lock().await;
-        let mut target_index = 0;
-        for element in &config_res.targets {
-            copyied_deploy_targets.push(element.clone());
+        copyied_deploy_targets = config_res.targets.clone();
+        max_parallel = config_res.max_parallel.unwrap_or(usize::MAX).max(1);
+        rollout = config_res.rollout.clone().unwrap_or_default();
+        canary = config_res.canary.clone().unwrap_or_default();
+    }
+    register_targets(&ui_state, &copyied_deploy_targets).await;
+    let hash_cache = Arc::new(HashCache::default());
+    let archive_cache = Arc::new(ArchiveCache::new()?);
+
+    // canaries go first and have to pass health check before the rest of the fleet
+    let (canary_targets, indexed_targets): (Vec<_>, Vec<_>) = (0u32..)
+        .zip(copyied_deploy_targets.iter().cloned())
+        .partition(|(_, target)| canary.is_canary(target));
+
+    let mut results: Vec<(u32, TargetResult)> = Vec::new();
+    let mut halted: Option<String> = None;
+    if !canary_targets.is_empty() {
+        {
+            let ui_state_res = ui_state.lock().await;
+            ui_state_res.emit(DeployEvent::CanaryStarted {
+                targets: canary_targets
+                    .iter()
+                    .map(|(_, t)| target_label(t).0)
+                    .collect(),
+            });
+        }
 
-            let name = element.name.to_owned().unwrap_or(String::from("unnamed"));
+        let canary_results = deploy_wave(
+            &config,
+            &ui_state,
+            &hash_cache,
+            &archive_cache,
+            &canary_targets,
+            max_parallel,
+        )
+        .await;
+        let mut canary_results = check_canaries(&config, &ui_state, &canary, canary_results).await;
+        if let Some((_, failed)) = canary_results
+            .iter()
+            .find(|(_, result)| result.outcome != TargetOutcome::Succeeded)
+        {
+            halted = Some(format!("skipped, canary {} failed", failed.name));
+        }
+        results.append(&mut canary_results);
+    }
 
-            {
-                let target = TargetState {
-                    state: UITargetState::TARGET_START,
-                    name: name,
-                    upload_package: "none".to_string(),
-                    upload_pos: 0,
-                    upload_len: 0,
-                };
-                let mut ui_state_res = ui_state.lock().await;
-                ui_state_res.set_deployment_target(target_index.clone(), target);
+    // 2. deploy targets in waves, next wave starts once previous one is finished
+    let waves: Vec<&[(u32, DeployTarget)]> = indexed_targets
+        .chunks(rollout.wave_size(indexed_targets.len()))
+        .collect();
+
+    for (wave_index, wave) in waves.iter().enumerate() {
+        if let Some(reason) = &halted {
+            for (target_index, deploy_target) in wave.iter() {
+                let err = anyhow!("{}", reason);
+                mark_target_failed(&ui_state, *target_index, &err).await;
+                let (name, host) = target_label(deploy_target);
+                results.push((*target_index, TargetResult::failed(name, host, &err)));
             }
+            continue;
+        }
 
-            target_index = target_index + 1;
+        if waves.len() > 1 {
+            let ui_state_res = ui_state.lock().await;
+            ui_state_res.emit(DeployEvent::WaveStarted {
+                wave: wave_index + 1,
+                waves: waves.len(),
+                targets: wave.iter().map(|(_, t)| target_label(t).0).collect(),
+            });
         }
-    }
 
-    // 2. deploy each target
-    let mut target_index = 0;
-    let mut deploy_tasks = Vec::new();
-    for deploy_target in &copyied_deploy_targets {
-        deploy_tasks.push(tokio::spawn(deploy(
-            config.clone(),
-            ui_state.clone(),
-            deploy_target.clone(),
-            target_index.clone(),
-        )));
-        target_index = target_index + 1;
+        let mut wave_results = deploy_wave(
+            &config,
+            &ui_state,
+            &hash_cache,
+            &archive_cache,
+            wave,
+            max_parallel,
+        )
+        .await;
+        let wave_failed = wave_results
+            .iter()
+            .any(|(_, result)| result.outcome != TargetOutcome::Succeeded);
+        results.append(&mut wave_results);
+
+        if wave_failed && rollout.halt_on_failure() {
+            halted = Some(format!(
+                "skipped, rollout halted after wave {} failed",
+                wave_index + 1
+            ));
+        }
     }
 
-    join_all(deploy_tasks).await;
+    results.sort_by_key(|(target_index, _)| *target_index);
+    let summary = DeploymentSummary {
+        targets: results.into_iter().map(|(_, result)| result).collect(),
+    };
 
     {
         let mut ui_state_res = ui_state.lock().await;
-        ui_state_res.set_screen(UIScreen::FINISHED);
+        ui_state_res
+            .set_deployed_count(summary.succeeded_count())
+            .set_failed_count(summary.failed_count())
+            .set_screen(UIScreen::FINISHED);
+        ui_state_res.emit(DeployEvent::DeploymentFinished {
+            succeeded: summary.succeeded_count(),
+            failed: summary.failed_count(),
+        });
     }
 
     //sleep(Duration::from_millis(500)).await;
@@ -92,7 +179,167 @@ pub async fn begin_deployment(
         ui_state_res.set_screen(UIScreen::FINISHED_END);
     }
 
-    Ok(())
+    Ok(summary)
+}
+
+async fn deploy_wave(
+    config: &Arc<Mutex<Config>>,
+    ui_state: &Arc<Mutex<UIStore>>,
+    hash_cache: &Arc<HashCache>,
+    archive_cache: &Arc<ArchiveCache>,
+    wave: &[(u32, DeployTarget)],
+    max_parallel: usize,
+) -> Vec<(u32, TargetResult)> {
+    // tasks are spawned lazily, so no more than max_parallel targets deploy at once
+    let config = config.clone();
+    let ui_state_tasks = ui_state.clone();
+    let hash_cache = hash_cache.clone();
+    let archive_cache = archive_cache.clone();
+    let results: Vec<_> = stream::iter(wave.to_vec())
+        .map(move |(target_index, deploy_target)| {
+            tokio::spawn(deploy(
+                config.clone(),
+                ui_state_tasks.clone(),
+                hash_cache.clone(),
+                archive_cache.clone(),
+                deploy_target,
+                target_index,
+            ))
+        })
+        .buffered(max_parallel)
+        .collect()
+        .await;
+
+    let mut target_results = Vec::new();
+    for ((target_index, deploy_target), res) in wave.iter().zip(results) {
+        let (name, host) = target_label(deploy_target);
+        let target_result = match res {
+            Ok(Ok(())) => TargetResult::succeeded(name, host),
+            Ok(Err(e)) => TargetResult::failed(name, host, &e), // already reported by deploy
+            Err(e) => {
+                let err = anyhow!("deployment task crashed: {}", e);
+                mark_target_failed(ui_state, *target_index, &err).await;
+                TargetResult::failed(name, host, &err)
+            }
+        };
+        target_results.push((*target_index, target_result));
+    }
+    target_results
+}
+
+/// Runs canary health check on deployed canaries right away and once more after soak time,
+/// rollout continues only if every canary stays healthy.
+async fn check_canaries(
+    config: &Arc<Mutex<Config>>,
+    ui_state: &Arc<Mutex<UIStore>>,
+    canary: &Canary,
+    mut results: Vec<(u32, TargetResult)>,
+) -> Vec<(u32, TargetResult)> {
+    let config = config.lock().await.clone();
+
+    let mut rounds = vec![0];
+    if canary.soak_seconds() > 0 {
+        rounds.push(canary.soak_seconds());
+    }
+    for soak_seconds in rounds {
+        if results
+            .iter()
+            .any(|(_, r)| r.outcome != TargetOutcome::Succeeded)
+        {
+            break;
+        }
+
+        if soak_seconds > 0 {
+            ui_state.lock().await.emit(DeployEvent::CanarySoak {
+                seconds: soak_seconds,
+            });
+            sleep(Duration::from_secs(soak_seconds)).await;
+        }
+
+        let Some(check) = &canary.health_check else {
+            continue;
+        };
+        for (target_index, result) in results.iter_mut() {
+            let Some(target) = config.targets.get(*target_index as usize) else {
+                continue;
+            };
+
+            let res = run_health_check(&config, target, check).await;
+            {
+                let ui_state_res = ui_state.lock().await;
+                ui_state_res.emit(DeployEvent::HealthCheckFinished {
+                    index: *target_index,
+                    target: result.name.to_string(),
+                    passed: res.is_ok(),
+                    error: res.as_ref().err().map(|e| format!("{:#}", e)),
+                });
+            }
+            if let Err(e) = res {
+                mark_target_failed(ui_state, *target_index, &e).await;
+                *result =
+                    TargetResult::failed(result.name.to_string(), result.host.to_string(), &e);
+            }
+        }
+    }
+    results
+}
+
+fn target_label(target: &DeployTarget) -> (String, String) {
+    let name = target.name.to_owned().unwrap_or(String::from("unnamed"));
+    (name, target.host.to_string())
+}
+
+pub async fn begin_plan(
+    config: Arc<Mutex<Config>>,
+    ui_state: Arc<Mutex<UIStore>>,
+) -> anyhow::Result<Vec<TargetPlan>> {
+    let copyied_deploy_targets: Vec<DeployTarget>;
+    {
+        let config_res = config.lock().await;
+        copyied_deploy_targets = config_res.targets.clone();
+    }
+    register_targets(&ui_state, &copyied_deploy_targets).await;
+    let hash_cache = Arc::new(HashCache::default());
+    let archive_cache = Arc::new(ArchiveCache::new()?);
+
+    let mut plan_tasks = Vec::new();
+    for (target_index, deploy_target) in (0u32..).zip(&copyied_deploy_targets) {
+        plan_tasks.push(tokio::spawn(plan(
+            config.clone(),
+            ui_state.clone(),
+            hash_cache.clone(),
+            archive_cache.clone(),
+            deploy_target.clone(),
+            target_index,
+        )));
+    }
+
+    let results = join_all(plan_tasks).await;
+
+    let mut plans = Vec::new();
+    for (deploy_target, res) in copyied_deploy_targets.iter().zip(results) {
+        let name = deploy_target
+            .name
+            .to_owned()
+            .unwrap_or(String::from("unnamed"));
+        let host = deploy_target.host.to_string();
+        plans.push(match res {
+            Ok(Ok(packages)) => TargetPlan {
+                result: TargetResult::succeeded(name, host),
+                packages,
+            },
+            Ok(Err(e)) => TargetPlan {
+                result: TargetResult::failed(name, host, &e),
+                packages: Vec::new(),
+            },
+            Err(e) => TargetPlan {
+                result: TargetResult::failed(name, host, &anyhow!("plan task crashed: {}", e)),
+                packages: Vec::new(),
+            },
+        });
+    }
+
+    Ok(plans)
 }
 
 pub async fn create_session(
@@ -100,13 +347,14 @@ pub async fn create_session(
     auth_str: String,
     host: String,
     port: u16,
+    verifier: HostKeyVerifier,
 ) -> anyhow::Result<Handle<Client>, anyhow::Error> {
     let mut session: Handle<Client>;
 
     // ssh config
     let ssh_config = russh::client::Config::default();
     let ssh_config = Arc::new(ssh_config);
-    let sh = Client {};
+    let sh = Client { verifier };
 
     session = russh::client::connect(ssh_config, (host.to_owned(), port), sh).await?;
 
@@ -118,7 +366,14 @@ pub async fn create_session(
             if creds.len() > 2 {
                 cert_pass = Some(creds[2]);
             }
-            let key = Arc::new(russh::keys::load_secret_key(creds[1], cert_pass).unwrap()); // should panic if key wrong
+            let key = Arc::new(
+                russh::keys::load_secret_key(creds[1], cert_pass).map_err(|e| {
+                    ClientError::AuthenticationFailed(format!(
+                        "{} (can't load key {}: {})",
+                        creds[0], creds[1], e
+                    ))
+                })?,
+            );
             let pre_res = session
                 .authenticate_publickey(
                     creds[0],
@@ -130,153 +385,558 @@ pub async fn create_session(
                 .await?;
             auth = pre_res == AuthResult::Success;
         }
-        "password" => match session.authenticate_password(creds[0], creds[1]).await {
-            Err(_) => return Err(anyhow!("authentication failed")),
-            Ok(res) => {
-                auth = res == AuthResult::Success;
-            }
-        },
+        "password" => {
+            // transport errors stay retryable, only a refused password fails authentication
+            let res = session.authenticate_password(creds[0], creds[1]).await?;
+            auth = res == AuthResult::Success;
+        }
         _ => {}
     }
 
     if !auth {
-        return Err(anyhow!("authentication failed #2"));
+        return Err(ClientError::AuthenticationFailed(creds[0].to_string()).into());
     }
 
     Ok(session)
 }
 
+async fn register_targets(ui_state: &Arc<Mutex<UIStore>>, targets: &[DeployTarget]) {
+    for (target_index, element) in (0u32..).zip(targets) {
+        let name = element.name.to_owned().unwrap_or(String::from("unnamed"));
+
+        let target = TargetState {
+            state: UITargetState::TARGET_START,
+            name,
+            upload_package: "none".to_string(),
+            upload_pos: 0,
+            upload_len: 0,
+            checksum_files: 0,
+            checksum_files_len: 0,
+            checksum_bytes: 0,
+            checksum_bytes_len: 0,
+            error: None,
+            failed_at: None,
+        };
+        let mut ui_state_res = ui_state.lock().await;
+        ui_state_res.set_deployment_target(target_index, target);
+    }
+}
+
+/// Opens ssh session to target, retrying according to its retry policy.
+pub async fn open_session(
+    config: &Config,
+    target: &DeployTarget,
+) -> anyhow::Result<Handle<Client>, anyhow::Error> {
+    let verifier = HostKeyVerifier::new(config, target);
+    let retry_policy = target
+        .retry
+        .unwrap_or_default()
+        .merge(&config.retry.unwrap_or_default());
+
+    let mut retry = Retry::new(retry_policy);
+    loop {
+        match connect_target(target, verifier.clone()).await {
+            Ok(session) => return Ok(session),
+            Err(e) if is_auth_failure(&e) => return Err(e),
+            Err(e) => retry.backoff(e).await?,
+        }
+    }
+}
+
+async fn connect_target(
+    target: &DeployTarget,
+    verifier: HostKeyVerifier,
+) -> anyhow::Result<Handle<Client>, anyhow::Error> {
+    // parse credentials
+    let (auth_type, auth_str) = target
+        .authentication
+        .iter()
+        .next()
+        .ok_or_else(|| anyhow!("no authentication configured"))?;
+    create_session(
+        auth_type.to_string(),
+        auth_str.to_string(),
+        target.host.to_string(),
+        target.port,
+        verifier,
+    )
+    .await
+}
+
+/// Lists files present in package's live directory on target but missing locally,
+/// protected and excluded paths and `nested` directories of other packages are never listed.
+async fn list_deletions(
+    session: &Handle<Client>,
+    package: &str,
+    package_element: &DeployPackage,
+    local_files: &[String],
+    nested: &[String],
+) -> anyhow::Result<Vec<String>, anyhow::Error> {
+    let protect = package_element.protect_set()?;
+    let filter = PackageFilter::new(package_element)?;
+    let script = format!(
+        "cd {} 2>/dev/null || exit 0; find . -type f -print0",
+        shell_quote(&package_element.live_directory())
+    );
+    let fmt = format!("{}sh -c {}", SUDO_PREPEND, shell_quote(&script));
+    let output = run_named_action(session, package, "list remote files", &fmt).await?;
+
+    let local_files: HashSet<&str> = local_files.iter().map(String::as_str).collect();
+    let mut deletions: Vec<String> = output
+        .stdout
+        .split('\0')
+        .filter_map(|path| path.strip_prefix("./"))
+        .filter(|path| !local_files.contains(path) && !protect.is_match(path))
+        .filter(|path| filter.is_included(path))
+        .filter(|path| !nested.iter().any(|dir| path.starts_with(dir.as_str())))
+        .map(String::from)
+        .collect();
+    deletions.sort();
+    Ok(deletions)
+}
+
+/// Length of partially uploaded archive on target when it matches start of local archive,
+/// with hasher fed by that part, 0 when upload has to start over.
+async fn resume_offset(
+    session: &Handle<Client>,
+    sftp: &SftpSession,
+    remote_path: &str,
+    archive_path: &Path,
+    total_size: u64,
+) -> (u64, Sha1) {
+    let Ok(metadata) = sftp.metadata(remote_path).await else {
+        return (0, Sha1::new());
+    };
+    let size = metadata.len();
+    if size == 0 || size > total_size {
+        return (0, Sha1::new());
+    }
+
+    let Ok(remote_hash) = remote_sha1(session, remote_path, Some(size)).await else {
+        return (0, Sha1::new());
+    };
+
+    let archive_path = archive_path.to_path_buf();
+    let hasher =
+        tokio::task::spawn_blocking(move || PackageCreator::hash_file_prefix(&archive_path, size))
+            .await;
+    match hasher {
+        Ok(Ok(hasher)) if PackageCreator::hex_digest(hasher.clone()) == remote_hash => {
+            (size, hasher)
+        }
+        _ => (0, Sha1::new()),
+    }
+}
+
+/// Rejected host key or credentials, retrying would only repeat the failed attempt
+/// (and could get the account locked out).
+fn is_auth_failure(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<ClientError>(),
+        Some(ClientError::HostKeyRejected(_)) | Some(ClientError::AuthenticationFailed(_))
+    )
+}
+
+async fn mark_target_failed(
+    ui_state: &Arc<Mutex<UIStore>>,
+    target_index: u32,
+    err: &anyhow::Error,
+) {
+    let mut ui_state_res = ui_state.lock().await;
+    ui_state_res.fail_target(target_index, format!("{:#}", err));
+}
+
 pub async fn deploy(
     config: Arc<Mutex<Config>>,
     ui_state: Arc<Mutex<UIStore>>,
+    hash_cache: Arc<HashCache>,
+    archive_cache: Arc<ArchiveCache>,
     target: DeployTarget,
     target_index: u32,
 ) -> anyhow::Result<(), anyhow::Error> {
-    // parse credentials
-    let (auth_type, auth_str) = target.authentication.iter().next().unwrap(); // should panic if config has errors
-    let mut target_package_names: HashMap<String, String> = HashMap::new();
-    let mut checksums: HashMap<String, HashMap<String, String>> = HashMap::new();
-    let mut deploy_states_uploaded: HashMap<String, bool> = HashMap::new();
-    let mut deploy_states_post_action_successed: HashMap<String, bool> = HashMap::new();
+    let mut deployment = TargetDeployment::new(
+        config,
+        ui_state.clone(),
+        hash_cache,
+        archive_cache,
+        target,
+        target_index,
+    )
+    .await;
+    let res = deployment.run().await;
+    if let Err(e) = &res {
+        mark_target_failed(&ui_state, target_index, e).await;
+    }
+    res
+}
 
-    'pre_deploy_connection: loop {
-        // create ssh session
-        let session: Handle<Client> = match create_session(
-            auth_type.to_string(),
-            auth_str.to_string(),
-            target.host.to_string(),
-            target.port,
-        )
-        .await
-        {
-            Ok(res) => res,
-            Err(_) => {
-                continue 'pre_deploy_connection;
-            }
-        };
-        // 3. deploy packages
+pub async fn plan(
+    config: Arc<Mutex<Config>>,
+    ui_state: Arc<Mutex<UIStore>>,
+    hash_cache: Arc<HashCache>,
+    archive_cache: Arc<ArchiveCache>,
+    target: DeployTarget,
+    target_index: u32,
+) -> anyhow::Result<Vec<PackagePlan>, anyhow::Error> {
+    let mut deployment = TargetDeployment::new(
+        config,
+        ui_state.clone(),
+        hash_cache,
+        archive_cache,
+        target,
+        target_index,
+    )
+    .await;
+    let res = deployment.plan().await;
+    if let Err(e) = &res {
+        mark_target_failed(&ui_state, target_index, e).await;
+    }
+    res
+}
+
+struct TargetDeployment {
+    config: Arc<Mutex<Config>>,
+    ui_state: Arc<Mutex<UIStore>>,
+    hash_cache: Arc<HashCache>,
+    archive_cache: Arc<ArchiveCache>,
+    target: DeployTarget,
+    target_index: u32,
+    verifier: HostKeyVerifier,
+    retry_policy: RetryPolicy,
+    verify: bool,
+
+    // progress, kept between reconnects
+    target_package_names: HashMap<String, String>,
+    checksums: HashMap<String, HashMap<String, String>>,
+    local_hashes: HashMap<String, HashMap<String, FileHash>>,
+    ongoing_deploy_packages_state: Vec<String>,
+    deploy_states_uploaded: HashMap<String, bool>,
+    deploy_states_post_action_successed: HashMap<String, bool>,
+    package_changes: HashMap<String, Vec<FileChange>>,
+    deletions: HashMap<String, Vec<String>>,
+    deploy_ids: HashMap<String, String>,
+    backed_up_packages: HashSet<String>,
+    deployed_packages: HashSet<String>,
+    outdated_manifests: HashSet<String>,
+    checked_codecs: HashSet<CompressionCodec>,
+    failed_packages: Vec<String>,
+}
+
+impl TargetDeployment {
+    async fn new(
+        config: Arc<Mutex<Config>>,
+        ui_state: Arc<Mutex<UIStore>>,
+        hash_cache: Arc<HashCache>,
+        archive_cache: Arc<ArchiveCache>,
+        target: DeployTarget,
+        target_index: u32,
+    ) -> TargetDeployment {
+        let verifier: HostKeyVerifier;
+        let retry_policy: RetryPolicy;
+        let verify: bool;
         {
-            let mut ui_state_res = ui_state.lock().await;
-            let target_state = ui_state_res
-                .deployment_targets
-                .get_mut(&target_index)
-                .unwrap();
-            target_state.state = UITargetState::TARGET_CHECKSUM;
-            target_state.upload_package = "".to_string();
-        }
-
-        for package in &target.packages {
-            // check if we already deployed package
-            if target_package_names.contains_key(package) {
-                continue;
-            }
+            let config_res = config.lock().await;
+            verify = config_res.verify;
+            verifier = HostKeyVerifier::new(&config_res, &target);
+            retry_policy = target
+                .retry
+                .unwrap_or_default()
+                .merge(&config_res.retry.unwrap_or_default());
+        }
 
-            let mut is_shit_happend = false;
-            let mut tmp_file_name = String::new();
-            {
-                let mut channel = match session.channel_open_session().await {
-                    Ok(r) => r,
-                    Err(_) => continue 'pre_deploy_connection,
-                };
+        TargetDeployment {
+            config,
+            ui_state,
+            hash_cache,
+            archive_cache,
+            target,
+            target_index,
+            verifier,
+            retry_policy,
+            verify,
+            target_package_names: HashMap::new(),
+            checksums: HashMap::new(),
+            local_hashes: HashMap::new(),
+            ongoing_deploy_packages_state: Vec::new(),
+            deploy_states_uploaded: HashMap::new(),
+            deploy_states_post_action_successed: HashMap::new(),
+            package_changes: HashMap::new(),
+            deletions: HashMap::new(),
+            deploy_ids: HashMap::new(),
+            backed_up_packages: HashSet::new(),
+            deployed_packages: HashSet::new(),
+            outdated_manifests: HashSet::new(),
+            checked_codecs: HashSet::new(),
+            failed_packages: Vec::new(),
+        }
+    }
 
-                match channel.exec(true, "mktemp").await {
-                    Err(_) => continue 'pre_deploy_connection,
-                    _ => {}
-                }; // ignore sudo here (important)
-                   //let mut is_msg_read = false;
-                while let Some(res) = channel.wait().await {
-                    match res {
-                        russh::ChannelMsg::ExtendedData { ref data, ext } => {
-                            if ext == 1 {
-                                is_shit_happend = true;
-                            }
-                            match std::str::from_utf8(data) {
-                                Ok(v) => {
-                                    tmp_file_name += v;
-                                }
-                                Err(_) => {
-                                    continue 'pre_deploy_connection;
-                                }
-                            };
-                        }
-                        russh::ChannelMsg::Data { ref data } => {
-                            match std::str::from_utf8(data) {
-                                Ok(v) => {
-                                    let st = v.to_string();
-                                    let dt: Vec<&str> = st.split("\n").collect();
-                                    tmp_file_name = Some(dt[0]).unwrap_or("").to_string();
-                                }
-                                Err(_) => {
-                                    continue 'pre_deploy_connection;
-                                }
-                            };
-                        }
-                        russh::ChannelMsg::Eof => {
-                            //is_msg_read = true;
-                            //break;
-                        }
-                        _ => {}
-                    }
+    async fn run(&mut self) -> anyhow::Result<(), anyhow::Error> {
+        self.set_target_state(UITargetState::TARGET_START, None)
+            .await;
+
+        // 1. compute remote checksums
+        let mut retry = Retry::new(self.retry_policy);
+        while let Err(e) = self.pre_deploy().await {
+            self.backoff(&mut retry, e).await?;
+        }
+
+        // 2. prepare & upload packages
+        let mut retry = Retry::new(self.retry_policy);
+        while let Err(e) = self.upload_packages().await {
+            self.backoff(&mut retry, e).await?;
+        }
+
+        // 3. extract packages & run actions
+        let mut retry = Retry::new(self.retry_policy);
+        while let Err(e) = self.post_deploy().await {
+            self.backoff(&mut retry, e).await?;
+        }
+
+        if !self.failed_packages.is_empty() {
+            return Err(anyhow!(self.failed_packages.join("; ")));
+        }
+
+        self.set_target_state(UITargetState::TARGET_FINISHED, None)
+            .await;
+
+        Ok(())
+    }
+
+    async fn plan(&mut self) -> anyhow::Result<Vec<PackagePlan>, anyhow::Error> {
+        self.set_target_state(UITargetState::TARGET_START, None)
+            .await;
+
+        // 1. compute remote checksums, nothing is uploaded or executed afterwards
+        let mut retry = Retry::new(self.retry_policy);
+        while let Err(e) = self.pre_deploy().await {
+            self.backoff(&mut retry, e).await?;
+        }
+
+        let mut packages = Vec::new();
+        for package in &self.target.packages {
+            let package_element = self.package(package).await?;
+            let server_hashes = self.checksums.get(package).cloned().unwrap_or_default();
+            let changes =
+                PackageCreator::new(&server_hashes).compute_changes(&self.local_hashes[package]);
+            let deletions = self.deletions.get(package).cloned().unwrap_or_default();
+
+            // actions run only when package has something to extract or delete
+            let actions = |actions: &Option<Vec<DeployAction>>| -> Vec<String> {
+                if changes.is_empty() && deletions.is_empty() {
+                    return Vec::new();
                 }
-            }
-            if is_shit_happend {
-                continue 'pre_deploy_connection;
-            }
+                actions
+                    .iter()
+                    .flatten()
+                    .map(|action| action.command().to_string())
+                    .collect()
+            };
+
+            packages.push(PackagePlan {
+                package: package.to_string(),
+                target_directory: package_element.target_directory.to_string(),
+                pre_deploy_actions: actions(&package_element.pre_deploy_actions),
+                post_deploy_actions: actions(&package_element.post_deploy_actions),
+                changes,
+                deletions,
+            });
+        }
 
-            // #USE_REMOTE_CHECKSUM
-            {
-                let mut ui_state_res = ui_state.lock().await;
-                let target_state = ui_state_res
-                    .deployment_targets
-                    .get_mut(&target_index)
-                    .unwrap();
-                target_state.upload_package = package.to_string()
+        self.set_target_state(UITargetState::TARGET_FINISHED, None)
+            .await;
+
+        Ok(packages)
+    }
+
+    async fn backoff(&self, retry: &mut Retry, err: anyhow::Error) -> anyhow::Result<()> {
+        // rejected host key or credentials and failed actions won't fix themselves on reconnect
+        if is_auth_failure(&err) || err.is::<ActionError>() {
+            return Err(err);
+        }
+        retry.backoff(err).await
+    }
+
+    async fn connect(&self) -> anyhow::Result<Handle<Client>, anyhow::Error> {
+        connect_target(&self.target, self.verifier.clone()).await
+    }
+
+    async fn package(&self, package: &str) -> anyhow::Result<DeployPackage, anyhow::Error> {
+        let config_res = self.config.lock().await;
+        match config_res.packages.get(package) {
+            Some(package_element) => Ok(package_element.clone()),
+            None => Err(anyhow!("package {} is not defined", package)),
+        }
+    }
+
+    /// Target directories of other packages of the target inside live directory of the package,
+    /// relative to it and ending with `/`.
+    async fn nested_directories(
+        &self,
+        package: &str,
+        package_element: &DeployPackage,
+    ) -> Vec<String> {
+        let live_directory = format!(
+            "{}/",
+            package_element.live_directory().trim_end_matches('/')
+        );
+        let config_res = self.config.lock().await;
+        self.target
+            .packages
+            .iter()
+            .filter(|other| *other != package)
+            .filter_map(|other| config_res.packages.get(other))
+            .filter_map(|other| {
+                let dir = format!("{}/", other.target_directory.trim_end_matches('/'));
+                dir.strip_prefix(&live_directory)
+                    .filter(|dir| !dir.is_empty())
+                    .map(String::from)
+            })
+            .collect()
+    }
+
+    /// Hashes local files of the package on blocking pool, unchanged files are taken from
+    /// cache shared by all targets, progress is reported while hashing.
+    async fn hash_local_files(
+        &mut self,
+        package: &str,
+        package_element: &DeployPackage,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        if self.local_hashes.contains_key(package) {
+            return Ok(());
+        }
+
+        let progress = Arc::new(HashProgress::default());
+        let hash_cache = self.hash_cache.clone();
+        let hashes = {
+            let hashing = hash_cache.hash_package(package, package_element, progress.clone());
+            tokio::pin!(hashing);
+            let mut ticker = tokio::time::interval(Duration::from_millis(100));
+            loop {
+                tokio::select! {
+                    res = &mut hashing => break res?,
+                    _ = ticker.tick() => self.set_checksum_progress(package, &progress).await,
+                }
             }
+        };
+        self.set_checksum_progress(package, &progress).await;
+        self.local_hashes.insert(package.to_string(), hashes);
+        Ok(())
+    }
+
+    async fn set_checksum_progress(&self, package: &str, progress: &HashProgress) {
+        let files = (
+            progress.files.load(Ordering::Relaxed),
+            progress.files_len.load(Ordering::Relaxed),
+        );
+        let bytes = (
+            progress.bytes.load(Ordering::Relaxed),
+            progress.bytes_len.load(Ordering::Relaxed),
+        );
+        let mut ui_state_res = self.ui_state.lock().await;
+        ui_state_res.update_checksum_progress(self.target_index, package, files, bytes);
+    }
+
+    async fn compression(&self, package_element: &DeployPackage) -> Compression {
+        let config_res = self.config.lock().await;
+        config_res.compression(package_element)
+    }
 
-            if !checksums.contains_key(package) {
-                checksums.insert(package.to_string(), HashMap::new());
+    /// Makes sure target's tar can extract archives of the package, checked once per codec.
+    async fn check_compression(
+        &mut self,
+        session: &Handle<Client>,
+        package: &str,
+        package_element: &DeployPackage,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        let compression = self.compression(package_element).await;
+        if self.checked_codecs.contains(&compression.codec) {
+            return Ok(());
+        }
+        if let Some(check) = compression.preflight_command() {
+            let script = format!(
+                "{} || {{ echo \"tar on target can't extract {} archives\" >&2; exit 1; }}",
+                check, compression.codec
+            );
+            let fmt = format!("sh -c {}", shell_quote(&script));
+            let name = format!("{} support check", compression.codec);
+            run_named_action(session, package, &name, &fmt).await?;
+        }
+        self.checked_codecs.insert(compression.codec);
+        Ok(())
+    }
+
+    async fn set_target_state(&self, state: UITargetState, package: Option<&str>) {
+        let mut ui_state_res = self.ui_state.lock().await;
+        ui_state_res.update_target_state(self.target_index, state, package);
+    }
+
+    async fn emit(&self, event: DeployEvent) {
+        let ui_state_res = self.ui_state.lock().await;
+        ui_state_res.emit(event);
+    }
+
+    fn target_name(&self) -> String {
+        self.target
+            .name
+            .to_owned()
+            .unwrap_or(String::from("unnamed"))
+    }
+
+    async fn pre_deploy(&mut self) -> anyhow::Result<(), anyhow::Error> {
+        // create ssh session
+        let session = self.connect().await?;
+        let sftp = open_sftp(&session).await?;
+
+        self.set_target_state(UITargetState::TARGET_CHECKSUM, Some(""))
+            .await;
+
+        for package in &self.target.packages.clone() {
+            // check if we already computed checksums for package
+            if self.checksums.contains_key(package) {
+                continue;
             }
 
+            // #USE_REMOTE_CHECKSUM
+            self.set_target_state(UITargetState::TARGET_CHECKSUM, Some(package))
+                .await;
+
             // iterate through external files & try to compute all checksums
-            let package_element: DeployPackage;
-            {
-                let config_res = config.lock().await;
-                let package_element_base = &config_res.packages[package];
-                package_element = package_element_base.clone();
-            }
-            let mut files: Vec<String> = Vec::new();
-            PackageCreator::collect_files_ext(
-                package_element.local_directory.to_string(),
-                &mut files,
-            );
+            let package_element = self.package(package).await?;
+            self.check_compression(&session, package, &package_element)
+                .await?;
+            self.hash_local_files(package, &package_element).await?;
+            let mut files: Vec<String> = self.local_hashes[package].keys().cloned().collect();
+            files.sort();
             // #USE_REMOTE_CHECKSUM_ACCUMULATED_HASHER
 
+            // files recorded in manifest by previous deploy don't have to be hashed on target
+            let manifest = match self.verify {
+                true => None,
+                false => read_manifest(&sftp, &package_element.manifest_file(package))
+                    .await
+                    .filter(|manifest| !manifest.stale),
+            };
+            let package_checksums = self.checksums.entry(package.to_string()).or_default();
+            let mut unknown_files: Vec<&String> = Vec::new();
+            for file in &files {
+                match manifest.as_ref().and_then(|m| m.files.get(file)) {
+                    Some(entry) => {
+                        package_checksums.insert(file.to_string(), entry.hash.to_string());
+                    }
+                    None => unknown_files.push(file),
+                }
+            }
+            if !unknown_files.is_empty() {
+                self.outdated_manifests.insert(package.to_string());
+            }
+
+            let live_directory = package_element.live_directory();
             let mut cmdpars: Vec<String> = Vec::new();
             let mut cmdpars_buf = String::new();
             let mut cmdctr = 0;
-            for file in &files {
-                cmdpars_buf +=
-                    &format!(" \"{}{}\"", package_element.target_directory, file).to_string();
+            for file in &unknown_files {
+                cmdpars_buf += &format!(" {}", shell_quote(&(live_directory.to_string() + file)));
                 cmdctr += 1;
                 if cmdctr > CMD_FILES_LIMIT {
                     cmdpars.push(cmdpars_buf);
@@ -289,400 +949,540 @@ pub async fn deploy(
                 cmdpars.push(cmdpars_buf);
             }
 
-            let mut cmd_res: String = String::new();
-
-            for (_i, cmdpars_entry) in cmdpars.iter().enumerate() {
-                let mut is_shit_happend = false;
-                {
-                    let mut channel = match session.channel_open_session().await {
-                        Ok(r) => r,
-                        Err(_) => continue 'pre_deploy_connection,
-                    };
-                    let fmt = format!("{}sha1sum{}", SUDO_PREPEND, cmdpars_entry);
-                    match channel.exec(true, fmt).await {
-                        Err(_) => continue 'pre_deploy_connection,
-                        _ => {}
-                    };
-
-                    while let Some(res) = channel.wait().await {
-                        match res {
-                            russh::ChannelMsg::ExtendedData { ref data, ext } => {
-                                if ext == 1 {
-                                    is_shit_happend = true;
-                                }
-
-                                match std::str::from_utf8(data) {
-                                    Ok(v) => {
-                                        cmd_res += v;
-                                    }
-                                    Err(_) => {
-                                        continue 'pre_deploy_connection;
-                                    }
-                                };
-                            }
-                            russh::ChannelMsg::Data { ref data } => {
-                                match std::str::from_utf8(data) {
-                                    Ok(v) => {
-                                        cmd_res += v;
-                                    }
-                                    Err(_) => {
-                                        continue 'pre_deploy_connection;
-                                    }
-                                };
-                            }
-                            russh::ChannelMsg::Eof => {
-                                //is_msg_read = true;
-                                //break;
-                            }
-                            _ => continue,
-                        }
-                    }
-                }
+            let mut remote_checksums: HashMap<String, String> = HashMap::new();
+            for cmdpars_entry in &cmdpars {
+                let fmt = format!("{}sha1sum --{}", SUDO_PREPEND, cmdpars_entry);
+                // file sometimes missing (initial upload as an example, so this is expected),
+                // sha1sum reports it on stderr and prints nothing for it on stdout
+                let output = run_command(&session, &fmt).await?;
+                remote_checksums.extend(parse_sha1sum_output(&output.stdout));
+            }
 
-                if is_shit_happend {
-                    // file sometimes missing (initial upload as an example, so this is expected)
-                    //continue 'pre_deploy_connection;
+            for file in &unknown_files {
+                if let Some(sum) = remote_checksums.get(&(live_directory.to_string() + file)) {
+                    package_checksums.insert(file.to_string(), sum.to_string());
                 }
             }
+            // files missing on target have no checksum, treat them as changed
+            for file in &files {
+                package_checksums.entry(file.to_string()).or_default();
+            }
 
-            let cmd_y_res: Vec<&str> = cmd_res.split("\n").collect();
-            //panic!("cmd_res: {:?}", cmd_res);
-            for (i, cmyr) in cmd_y_res.iter().enumerate() {
-                if files.len() <= i {
-                    continue;
-                }
-                let sum_vec: Vec<&str> = cmyr.split(" ").collect();
-                let sum = sum_vec[0];
-                checksums.get_mut(package).unwrap().remove(&files[i]); // check for duplicates
-                checksums
-                    .get_mut(package)
-                    .unwrap()
-                    .insert(files[i].to_string(), sum.to_string());
+            if package_element.is_mirror() {
+                let nested = self.nested_directories(package, &package_element).await;
+                let deletions =
+                    list_deletions(&session, package, &package_element, &files, &nested).await?;
+                self.deletions.insert(package.to_string(), deletions);
             }
 
-            target_package_names.insert(package.to_string(), tmp_file_name);
+            self.emit(DeployEvent::ChecksumComputed {
+                index: self.target_index,
+                target: self.target_name(),
+                package: package.to_string(),
+                files: files.len(),
+            })
+            .await;
         }
 
-        break 'pre_deploy_connection;
+        Ok(())
     }
 
-    // 2. prepare & upload packages
-    let mut ongoing_deploy_packages_state: Vec<String> = Vec::new();
-    'ongoing_deploy_connection: loop {
-        let mut checksums_deep_copy: HashMap<String, HashMap<String, String>> = HashMap::new();
-        for (key, value) in &checksums {
-            checksums_deep_copy.insert(key.to_string(), HashMap::new());
-            for (k1, v1) in value {
-                checksums_deep_copy
-                    .get_mut(key)
-                    .unwrap()
-                    .insert(k1.to_string(), v1.to_string());
-            }
-        }
-
+    async fn upload_packages(&mut self) -> anyhow::Result<(), anyhow::Error> {
         // create ssh session
-        let session: Handle<Client> = match create_session(
-            auth_type.to_string(),
-            auth_str.to_string(),
-            target.host.to_string(),
-            target.port,
-        )
-        .await
-        {
-            Ok(res) => res,
-            Err(_) => {
-                continue 'ongoing_deploy_connection;
-            }
-        };
-
-        let channel = match session.channel_open_session().await {
-            Ok(r) => r,
-            Err(_) => continue 'ongoing_deploy_connection,
-        };
-
-        match channel.request_subsystem(true, "sftp").await {
-            Err(..) => continue 'ongoing_deploy_connection,
-            _ => {}
-        }
+        let session = self.connect().await?;
+        let sftp = open_sftp(&session).await?;
 
-        let sftp = match SftpSession::new(channel.into_stream()).await {
-            Err(..) => continue 'ongoing_deploy_connection,
-            Ok(res) => res,
-        };
-
-        for package in &target.packages {
-            if ongoing_deploy_packages_state.contains(package) {
+        for package in &self.target.packages {
+            if self.ongoing_deploy_packages_state.contains(package) {
                 continue;
             }
 
-            let package_element: DeployPackage;
-            {
-                let config_res = config.lock().await;
-                let package_element_base = &config_res.packages[package];
-                package_element = package_element_base.clone();
-            }
+            let package_element = self.package(package).await?;
+            let server_hashes = self.checksums.get(package).cloned().unwrap_or_default();
+            let creator = PackageCreator::new(&server_hashes);
+
+            let local_hashes = &self.local_hashes[package];
+            let changes = creator.compute_changes(local_hashes);
+
+            // targets with the same changes share one archive
+            let archive_path = match changes.is_empty() {
+                true => None,
+                false => {
+                    let files: Vec<(String, String)> = changes
+                        .iter()
+                        .map(|c| (c.path.to_string(), local_hashes[&c.path].hash.to_string()))
+                        .collect();
+                    let compression = self.compression(&package_element).await;
+                    let archive = self
+                        .archive_cache
+                        .archive(&package_element.local_directory, &files, compression)
+                        .await?;
+                    Some(archive)
+                }
+            };
+            self.package_changes.insert(package.to_string(), changes);
 
-            let creator = PackageCreator::new(checksums_deep_copy.get(package).unwrap());
+            if let Some(archive_path) = &archive_path {
+                // read local file
+                let mut file = tokio::fs::File::open(archive_path).await?;
+                let total_size = file.metadata().await?.len();
 
-            let local_temp_dir = tempfile::tempdir()?;
-            let local_temp_file_path = local_temp_dir.path().join("archive.tar.gz");
-            let local_temp_file_path_copy = local_temp_dir.path().join("archive.tar.gz");
-            let res: bool;
+                // upload interrupted by reconnect continues after the verified part
+                let (offset, mut hasher) = match self.target_package_names.get(package) {
+                    Some(remote_path) => {
+                        resume_offset(&session, &sftp, remote_path, archive_path, total_size).await
+                    }
+                    None => (0, Sha1::new()),
+                };
 
-            {
-                let local_temp_file = File::create(local_temp_file_path)?;
-                res = creator.prepare_package_for_target(
-                    &local_temp_file,
-                    package_element.local_directory.to_string(),
-                );
-            }
+                self.set_upload_progress(package, offset, total_size).await;
 
-            //, out byte[] hashes, out int writtenEntries);
-            if res {
-                // read local file
-                let file = tokio::fs::File::open(&local_temp_file_path_copy).await?;
-                let total_size = file.metadata().await.unwrap().len();
-                let mut reader_stream =
-                    tokio_util::io::ReaderStream::with_capacity(file, CHUNK_UPLOAD_BUFFER);
-
-                {
-                    let mut ui_state_res = ui_state.lock().await;
-                    let target_state = ui_state_res
-                        .deployment_targets
-                        .get_mut(&target_index)
-                        .unwrap();
-                    target_state.state = UITargetState::TARGET_UPLOADING;
-                    target_state.upload_package = package.to_string();
-                    target_state.upload_pos = 0;
-                    target_state.upload_len = total_size;
+                if !self.target_package_names.contains_key(package) {
+                    // ignore sudo here (important)
+                    let mktemp = run_command(&session, "mktemp").await?;
+                    if !mktemp.success() {
+                        return Err(anyhow!("mktemp failed: {}", mktemp.stderr.trim()));
+                    }
+                    let tmp_file_name = mktemp.stdout.lines().next().unwrap_or("").to_string();
+                    self.target_package_names
+                        .insert(package.to_string(), tmp_file_name);
                 }
 
                 // open remote file ()
-                let mut remote_file = match sftp
-                    .create(target_package_names.get(package).unwrap())
-                    .await
-                {
-                    Err(..) => continue 'ongoing_deploy_connection,
-                    Ok(res) => res,
+                let remote_path = self.target_package_names.get(package).unwrap();
+                let mut remote_file = match offset {
+                    0 => sftp.create(remote_path).await?,
+                    _ => {
+                        let mut remote_file =
+                            sftp.open_with_flags(remote_path, OpenFlags::WRITE).await?;
+                        remote_file.seek(SeekFrom::Start(offset)).await?;
+                        file.seek(SeekFrom::Start(offset)).await?;
+                        remote_file
+                    }
                 };
+                let mut reader_stream =
+                    tokio_util::io::ReaderStream::with_capacity(file, CHUNK_UPLOAD_BUFFER);
 
-                let mut uploaded = 0;
+                let mut uploaded = offset;
                 while let Some(chunk) = reader_stream.next().await {
-                    if let Ok(chunk) = &chunk {
-                        let mut chunk_upload_retries = 0;
-                        'upload_loop: loop {
-                            if chunk_upload_retries > CHUNK_UPLOAD_RETRIES {
-                                continue 'ongoing_deploy_connection;
-                            }
-
-                            let chunk_upload_res = remote_file.write_all(chunk).await;
-                            match chunk_upload_res {
-                                Ok(_) => {
-                                    break 'upload_loop;
-                                }
-                                Err(_) => {
-                                    chunk_upload_retries += 1;
-                                    continue 'upload_loop;
-                                }
-                            }
-                        }
-
-                        let new = min(uploaded + (chunk.len() as u64), total_size);
-                        uploaded = new;
-                        {
-                            let mut ui_state_res = ui_state.lock().await;
-                            let target_state = ui_state_res
-                                .deployment_targets
-                                .get_mut(&target_index)
-                                .unwrap();
-                            target_state.state = UITargetState::TARGET_UPLOADING;
-                            target_state.upload_pos = uploaded;
-                            target_state.upload_len = total_size;
+                    let chunk = chunk?;
+                    let mut chunk_upload_retries = 0;
+                    while let Err(e) = remote_file.write_all(&chunk).await {
+                        chunk_upload_retries += 1;
+                        if chunk_upload_retries > CHUNK_UPLOAD_RETRIES {
+                            return Err(anyhow!("upload of {} failed: {}", package, e));
                         }
                     }
-                }
 
-                deploy_states_uploaded.insert(package.to_string(), true);
-            } else {
-                {
-                    let mut ui_state_res = ui_state.lock().await;
-                    let target_state = ui_state_res
-                        .deployment_targets
-                        .get_mut(&target_index)
-                        .unwrap();
-                    target_state.state = UITargetState::TARGET_NO_CHANGES;
-                    target_state.upload_package = package.to_string();
+                    hasher.update(&chunk);
+                    uploaded = min(uploaded + (chunk.len() as u64), total_size);
+                    self.set_upload_progress(package, uploaded, total_size)
+                        .await;
+                }
+                // close remote file, so tar never sees a partially written archive
+                remote_file.shutdown().await?;
+
+                // never extract a corrupt archive, reconnect restarts upload of mismatching part
+                let local_hash = PackageCreator::hex_digest(hasher);
+                let remote_hash = remote_sha1(&session, remote_path, None).await?;
+                if local_hash != remote_hash {
+                    return Err(anyhow!(
+                        "uploaded archive of {} doesn't match local archive (sha1 {} on target, {} locally)",
+                        package,
+                        remote_hash,
+                        local_hash
+                    ));
                 }
+
+                self.deploy_states_uploaded
+                    .insert(package.to_string(), true);
+            } else if !self.deletions.contains_key(package) || self.deletions[package].is_empty() {
+                self.set_target_state(UITargetState::TARGET_NO_CHANGES, Some(package))
+                    .await;
             }
 
-            ongoing_deploy_packages_state.push(package.to_string());
+            self.ongoing_deploy_packages_state.push(package.to_string());
         }
 
-        break 'ongoing_deploy_connection;
+        Ok(())
+    }
+
+    async fn set_upload_progress(&self, package: &str, upload_pos: u64, upload_len: u64) {
+        let mut ui_state_res = self.ui_state.lock().await;
+        ui_state_res.update_upload_progress(self.target_index, package, upload_pos, upload_len);
     }
 
-    'post_deploy_connection: loop {
+    async fn post_deploy(&mut self) -> anyhow::Result<(), anyhow::Error> {
         // create ssh session
-        let session: Handle<Client> = match create_session(
-            auth_type.to_string(),
-            auth_str.to_string(),
-            target.host.to_string(),
-            target.port,
-        )
-        .await
-        {
-            Ok(res) => res,
-            Err(_) => {
-                continue 'post_deploy_connection;
-            }
-        };
+        let session = self.connect().await?;
 
-        for package in &target.packages {
-            {
-                let mut ui_state_res = ui_state.lock().await;
-                let target_state = ui_state_res
-                    .deployment_targets
-                    .get_mut(&target_index)
-                    .unwrap();
-                target_state.state = UITargetState::TARGET_FINISHING;
-                target_state.upload_package = package.to_string();
-            }
+        for package in &self.target.packages.clone() {
+            self.set_target_state(UITargetState::TARGET_FINISHING, Some(package))
+                .await;
 
-            let package_element: DeployPackage;
+            if self
+                .deploy_states_post_action_successed
+                .contains_key(package)
             {
-                let config_res = config.lock().await;
-                let package_element_base = &config_res.packages[package];
-                package_element = package_element_base.clone();
-            }
-
-            if deploy_states_post_action_successed.contains_key(package) {
                 continue;
             }
 
-            if deploy_states_uploaded.contains_key(package) {
-                // 3. execute pre deploy actions
-                for action in package_element.pre_deploy_actions.iter().flatten() {
-                    let mut channel = match session.channel_open_session().await {
-                        Ok(r) => r,
-                        Err(_) => continue 'post_deploy_connection,
-                    };
-                    let fmt = format!("{}", action);
-                    match channel.exec(true, fmt).await {
-                        Err(_) => continue 'post_deploy_connection,
-                        _ => {}
-                    };
-
-                    while let Some(res) = channel.wait().await {
-                        match res {
-                            russh::ChannelMsg::Data { data: _ } => {
-                                // std out
-                            }
-                            russh::ChannelMsg::ExtendedData { data: _, ext } => {
-                                if ext == 1 {
-                                    // std err
-                                }
-                            }
-                            russh::ChannelMsg::ExitStatus { exit_status: _ } => {}
-                            russh::ChannelMsg::Eof => {
-                                //break;
-                            }
-                            _ => continue,
-                        }
+            let package_element = self.package(package).await?;
+
+            let remote_archive = match self.deploy_states_uploaded.contains_key(package) {
+                true => Some(self.target_package_names[package].to_string()),
+                false => None,
+            };
+            let has_deletions = self
+                .deletions
+                .get(package)
+                .is_some_and(|deletions| !deletions.is_empty());
+
+            // a package deployed before a reconnect only needs its manifest written
+            let deploy = (remote_archive.is_some() || has_deletions)
+                && !self.deployed_packages.contains(package);
+            if deploy {
+                let res = self
+                    .deploy_package(
+                        &session,
+                        package,
+                        &package_element,
+                        remote_archive.as_deref(),
+                    )
+                    .await;
+
+                // connection problems are retried from the beginning of the package
+                if let Err(e) = &res {
+                    if !e.is::<ActionError>() {
+                        return res;
                     }
                 }
-                // 4. deploy package
-                {
-                    let mut channel = match session.channel_open_session().await {
-                        Ok(r) => r,
-                        Err(_) => continue 'post_deploy_connection,
-                    };
-                    /*let _ = channel
-                    .request_pty(false, "deploy", 100, 50, 0, 0, &[])
-                    .await;*/
-
-                    //"{}sh -c \"cd '{}';tar -xzf '{}'\"",
-                    let fmt = format!(
-                        "{}tar -xzf '{}' --directory '{}'",
-                        SUDO_PREPEND,
-                        target_package_names.get(package).unwrap(),
-                        package_element.target_directory
-                    );
-                    match channel.exec(true, fmt).await {
-                        Err(_) => continue 'post_deploy_connection,
-                        _ => {}
-                    };
-
-                    while let Some(_res) = channel.wait().await {}
-                }
 
-                // 5. execute post deploy actions
-                for action in package_element.post_deploy_actions.iter().flatten() {
-                    let mut channel = match session.channel_open_session().await {
-                        Ok(r) => r,
-                        Err(_) => continue 'post_deploy_connection,
-                    };
-                    let fmt = format!("{}", action);
-                    match channel.exec(true, fmt).await {
-                        Err(_) => continue 'post_deploy_connection,
-                        _ => {}
-                    };
-
-                    while let Some(res) = channel.wait().await {
-                        match res {
-                            russh::ChannelMsg::Eof => {
-                                //break;
-                            }
-                            _ => continue,
-                        }
-                    }
+                // 6. cleanup remote
+                if let Some(remote_archive) = &remote_archive {
+                    let fmt = format!("{}rm -f \"{}\"", SUDO_PREPEND, remote_archive);
+                    run_command(&session, &fmt).await?;
                 }
 
-                // 6. cleanup remote
-                {
-                    let mut channel = match session.channel_open_session().await {
-                        Ok(r) => r,
-                        Err(_) => continue 'post_deploy_connection,
-                    };
-                    let fmt = format!(
-                        "{}rm -f \"{}\"",
-                        SUDO_PREPEND,
-                        target_package_names.get(package).unwrap()
-                    );
-                    match channel.exec(true, fmt).await {
-                        Err(_) => continue 'post_deploy_connection,
-                        _ => {}
-                    };
-
-                    while let Some(res) = channel.wait().await {
-                        match res {
-                            russh::ChannelMsg::Eof => {
-                                //break;
-                            }
-                            _ => continue,
-                        }
+                if let Err(e) = res {
+                    self.deploy_states_post_action_successed
+                        .insert(package.to_string(), false);
+                    if package_element.abort_target_on_failure.unwrap_or(true) {
+                        return Err(e);
                     }
+                    self.failed_packages.push(format!("{:#}", e));
+                    continue;
                 }
+
+                self.deployed_packages.insert(package.to_string());
+                self.outdated_manifests.insert(package.to_string());
             }
 
-            deploy_states_post_action_successed.insert(package.to_string(), true);
+            // target now matches local files, record them for the next run,
+            // the package only counts as done once the manifest is written
+            if self.outdated_manifests.contains(package) {
+                let manifest = Manifest::from_hashes(&self.local_hashes[package]);
+                write_manifest(
+                    &session,
+                    package,
+                    &package_element.manifest_file(package),
+                    &manifest,
+                )
+                .await?;
+                self.outdated_manifests.remove(package);
+            }
+
+            self.deploy_states_post_action_successed
+                .insert(package.to_string(), true);
         }
 
-        for package in &target.packages {
-            deploy_states_post_action_successed.insert(package.to_string(), false);
-            // not much sense, but leave for now (ported from C#)
+        Ok(())
+    }
+
+    async fn deploy_package(
+        &mut self,
+        session: &Handle<Client>,
+        package: &str,
+        package_element: &DeployPackage,
+        remote_archive: Option<&str>,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        // 3. execute pre deploy actions
+        self.run_actions(session, package, &package_element.pre_deploy_actions)
+            .await?;
+
+        // 4. deploy package, manifest is no longer accurate until deploy succeeds
+        flag_manifest_stale(session, package, &package_element.manifest_file(package)).await?;
+        if let Some(releases) = &package_element.releases {
+            self.deploy_release(session, package, package_element, releases, remote_archive)
+                .await?;
+        } else {
+            if package_element.backup.unwrap_or(false) {
+                self.backup_package(session, package, package_element)
+                    .await?;
+            }
+
+            if let Some(remote_archive) = remote_archive {
+                //"{}sh -c \"cd '{}';tar -xzf '{}'\"",
+                let compression = self.compression(package_element).await;
+                let fmt = format!(
+                    "{}tar {} '{}' --directory '{}'",
+                    SUDO_PREPEND,
+                    compression.extract_args(),
+                    remote_archive,
+                    package_element.target_directory
+                );
+                run_action(session, package, &fmt).await?;
+            }
+
+            self.delete_files(session, package, &package_element.target_directory)
+                .await?;
         }
 
-        break 'post_deploy_connection;
+        // 5. execute post deploy actions
+        self.run_actions(session, package, &package_element.post_deploy_actions)
+            .await?;
+
+        Ok(())
     }
 
-    {
-        let mut ui_state_res = ui_state.lock().await;
-        let target_state = ui_state_res
-            .deployment_targets
-            .get_mut(&target_index)
-            .unwrap();
-        target_state.state = UITargetState::TARGET_FINISHED;
+    /// Timestamp identifying release or backup of the package, kept between reconnects,
+    /// so a retry doesn't create another one. Nanoseconds keep deploys within a second apart.
+    async fn deploy_id(
+        &mut self,
+        session: &Handle<Client>,
+        package: &str,
+    ) -> anyhow::Result<String, anyhow::Error> {
+        if !self.deploy_ids.contains_key(package) {
+            let date = run_command(session, "date -u +%Y%m%d%H%M%S-%N").await?;
+            let deploy_id = date.stdout.trim().to_string();
+            if !date.success() || deploy_id.is_empty() {
+                return Err(anyhow!("can't get deploy id: {}", date.stderr.trim()));
+            }
+            self.deploy_ids.insert(package.to_string(), deploy_id);
+        }
+        Ok(self.deploy_ids[package].to_string())
+    }
+
+    /// Archives remote files about to be overwritten and lists files about to be created,
+    /// so rollback can restore the target directory to its state before the deploy.
+    async fn backup_package(
+        &mut self,
+        session: &Handle<Client>,
+        package: &str,
+        package_element: &DeployPackage,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        // extraction may have started before a reconnect, rebuilding the backup
+        // would capture partially overwritten files
+        if self.backed_up_packages.contains(package) {
+            return Ok(());
+        }
+
+        let backup_id = self.deploy_id(session, package).await?;
+        let backup_dir = shell_quote(&package_element.backup_directory(package));
+        let changes = self
+            .package_changes
+            .get(package)
+            .cloned()
+            .unwrap_or_default();
+        let deletions = self.deletions.get(package).cloned().unwrap_or_default();
+
+        let fmt = format!(
+            "{}sh -c {}",
+            SUDO_PREPEND,
+            shell_quote(&format!(
+                "mkdir -p {dir} && chmod 700 {dir} && cd {dir} && : > {id}.modified && : > {id}.new",
+                dir = backup_dir,
+                id = backup_id
+            ))
+        );
+        run_named_action(session, package, "backup", &fmt).await?;
+
+        // file lists are appended in batches to keep command line short,
+        // files deleted in mirror mode are backed up like overwritten ones
+        for kind in [FileChangeKind::Modified, FileChangeKind::New] {
+            let list = match kind {
+                FileChangeKind::Modified => format!("{}.modified", backup_id),
+                FileChangeKind::New => format!("{}.new", backup_id),
+            };
+            let mut files: Vec<String> = changes
+                .iter()
+                .filter(|change| change.kind == kind)
+                .map(|change| shell_quote(&change.path))
+                .collect();
+            if kind == FileChangeKind::Modified {
+                files.extend(deletions.iter().map(|path| shell_quote(path)));
+            }
+            for batch in files.chunks(CMD_FILES_LIMIT as usize) {
+                let script = format!(
+                    "cd {} && printf '%s\\n' {} >> {}",
+                    backup_dir,
+                    batch.join(" "),
+                    list
+                );
+                let fmt = format!("{}sh -c {}", SUDO_PREPEND, shell_quote(&script));
+                run_named_action(session, package, "backup", &fmt).await?;
+            }
+        }
+
+        // record backup in journal, dropping the oldest ones,
+        // files which disappeared on target since checksum have nothing to back up
+        let script = format!(
+            "set -e\n\
+             tar -czf {dir}/{id}.tar.gz -C {target} --ignore-failed-read --files-from {dir}/{id}.modified\n\
+             cd {dir}\n\
+             grep -qx {id} journal 2>/dev/null || echo {id} >> journal\n\
+             n=$(wc -l < journal)\n\
+             if [ \"$n\" -gt {keep} ]; then\n\
+             head -n $((n - {keep})) journal | while read -r old; do rm -f \"$old.tar.gz\" \"$old.modified\" \"$old.new\"; done\n\
+             tail -n {keep} journal > journal.tmp && mv journal.tmp journal\n\
+             fi\n",
+            dir = backup_dir,
+            id = backup_id,
+            target = shell_quote(&package_element.target_directory),
+            keep = BACKUPS_KEEP,
+        );
+        let fmt = format!("{}sh -c {}", SUDO_PREPEND, shell_quote(&script));
+        run_named_action(session, package, &format!("backup {}", backup_id), &fmt).await?;
+        self.backed_up_packages.insert(package.to_string());
+        Ok(())
+    }
+
+    /// Extracts archive into a new release populated with hard links to the current one,
+    /// then atomically switches `current` symlink and removes old releases.
+    async fn deploy_release(
+        &mut self,
+        session: &Handle<Client>,
+        package: &str,
+        package_element: &DeployPackage,
+        releases: &Releases,
+        remote_archive: Option<&str>,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        let release = format!("releases/{}", self.deploy_id(session, package).await?);
+        let base = shell_quote(&package_element.target_directory);
+        let compression = self.compression(package_element).await;
+        let extract = match remote_archive {
+            Some(remote_archive) => format!(
+                "tar {} {} --directory {}\n",
+                compression.extract_args(),
+                shell_quote(remote_archive),
+                shell_quote(&release)
+            ),
+            None => String::new(),
+        };
+
+        // tar replaces existing files instead of writing through them,
+        // so hard links shared with previous release stay untouched
+        let script = format!(
+            "set -e\n\
+             cd {base}\n\
+             mkdir -p releases\n\
+             if [ \"$(readlink current 2>/dev/null)\" != {release} ]; then\n\
+             rm -rf {release}\n\
+             if [ -d current ]; then cp -al current/. {release}; else mkdir {release}; fi\n\
+             {extract}\
+             fi\n",
+            base = base,
+            release = shell_quote(&release),
+            extract = extract,
+        );
+        let fmt = format!("{}sh -c {}", SUDO_PREPEND, shell_quote(&script));
+        run_named_action(session, package, &format!("release {}", release), &fmt).await?;
+
+        let release_directory = format!(
+            "{}/{}",
+            package_element.target_directory.trim_end_matches('/'),
+            release
+        );
+        self.delete_files(session, package, &release_directory)
+            .await?;
+
+        let script = format!(
+            "set -e\n\
+             cd {base}\n\
+             ln -sfn {release} current.tmp\n\
+             mv -Tf current.tmp current\n\
+             ls -1 releases | sort | head -n -{keep} | while read -r old; do rm -rf \"releases/$old\"; done\n",
+            base = base,
+            release = shell_quote(&release),
+            keep = releases.keep(),
+        );
+        let fmt = format!("{}sh -c {}", SUDO_PREPEND, shell_quote(&script));
+        run_named_action(session, package, &format!("switch to {}", release), &fmt).await?;
+        Ok(())
     }
 
-    Ok(())
+    /// Removes files deleted locally in mirror mode along with directories left empty.
+    async fn delete_files(
+        &self,
+        session: &Handle<Client>,
+        package: &str,
+        directory: &str,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        let Some(deletions) = self.deletions.get(package) else {
+            return Ok(());
+        };
+
+        for batch in deletions.chunks(CMD_FILES_LIMIT as usize) {
+            let files: Vec<String> = batch.iter().map(|path| shell_quote(path)).collect();
+            let mut parents: Vec<String> = batch
+                .iter()
+                .filter_map(|path| path.rsplit_once('/').map(|(parent, _)| shell_quote(parent)))
+                .collect();
+            parents.sort();
+            parents.dedup();
+
+            let mut script = format!(
+                "set -e\ncd {}\nrm -f -- {}\n",
+                shell_quote(directory),
+                files.join(" ")
+            );
+            if !parents.is_empty() {
+                script += &format!(
+                    "rmdir -p --ignore-fail-on-non-empty -- {} 2>/dev/null || true\n",
+                    parents.join(" ")
+                );
+            }
+            let fmt = format!("{}sh -c {}", SUDO_PREPEND, shell_quote(&script));
+            run_named_action(session, package, "delete removed files", &fmt).await?;
+        }
+        Ok(())
+    }
+
+    async fn run_actions(
+        &self,
+        session: &Handle<Client>,
+        package: &str,
+        actions: &Option<Vec<DeployAction>>,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        for action in actions.iter().flatten() {
+            self.emit(DeployEvent::ActionStarted {
+                index: self.target_index,
+                target: self.target_name(),
+                package: package.to_string(),
+                command: action.command().to_string(),
+            })
+            .await;
+
+            let res = run_action(session, package, action.command()).await;
+            let exit_code = match &res {
+                Ok(output) => output.exit_status,
+                Err(e) => e.downcast_ref::<ActionError>().map(|e| e.exit_status),
+            };
+            self.emit(DeployEvent::ActionFinished {
+                index: self.target_index,
+                target: self.target_name(),
+                package: package.to_string(),
+                command: action.command().to_string(),
+                exit_code,
+            })
+            .await;
+
+            if let Err(e) = res {
+                if !(e.is::<ActionError>() && action.continue_on_error()) {
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,238 @@
+use super::packaging::PackageCreator;
+use crate::serialization::{deploy_package::DeployPackage, package_filter::HASH_CACHE_FILE};
+use futures::lock::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::UNIX_EPOCH,
+};
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct FileHash {
+    pub size: u64,
+    // modification time in nanoseconds since unix epoch
+    pub mtime: u64,
+    pub hash: String,
+}
+
+/// Hashing progress, updated by worker threads.
+#[derive(Default)]
+pub struct HashProgress {
+    pub files: AtomicU64,
+    pub files_len: AtomicU64,
+    pub bytes: AtomicU64,
+    pub bytes_len: AtomicU64,
+}
+
+impl HashProgress {
+    fn set_done(&self, hashes: &HashMap<String, FileHash>) {
+        let bytes = hashes.values().map(|entry| entry.size).sum();
+        self.files.store(hashes.len() as u64, Ordering::Relaxed);
+        self.files_len.store(hashes.len() as u64, Ordering::Relaxed);
+        self.bytes.store(bytes, Ordering::Relaxed);
+        self.bytes_len.store(bytes, Ordering::Relaxed);
+    }
+}
+
+// locked on its own, so work on one entry doesn't block others
+type HashEntry = Arc<Mutex<Option<HashMap<String, FileHash>>>>;
+
+/// Local file hashes keyed by path, size and mtime, persisted to `.deploy-cache`
+/// in package's local directory and shared by every target of the run.
+#[derive(Default)]
+pub struct HashCache {
+    // by local directory, read from `.deploy-cache` on first use
+    directories: Mutex<HashMap<String, HashEntry>>,
+    // finished hashes by package name, files are walked once per run
+    packages: Mutex<HashMap<String, HashEntry>>,
+}
+
+impl HashCache {
+    /// Collects and hashes files of the package on blocking pool,
+    /// files with unchanged size and mtime aren't reread.
+    pub async fn hash_package(
+        &self,
+        name: &str,
+        package: &DeployPackage,
+        progress: Arc<HashProgress>,
+    ) -> anyhow::Result<HashMap<String, FileHash>, anyhow::Error> {
+        // only the entry is locked while hashing, other packages are hashed concurrently
+        let entry = {
+            let mut packages = self.packages.lock().await;
+            packages.entry(name.to_string()).or_default().clone()
+        };
+        let mut entry = entry.lock().await;
+        if let Some(hashes) = entry.as_ref() {
+            progress.set_done(hashes);
+            return Ok(hashes.clone());
+        }
+
+        let local_dir = package.local_directory.to_string();
+        let directory = {
+            let mut directories = self.directories.lock().await;
+            directories
+                .entry(local_dir.to_string())
+                .or_default()
+                .clone()
+        };
+
+        // hash against a copy, packages sharing the directory don't wait for each other
+        let cached = directory.lock().await.clone();
+        let package = package.clone();
+        let (cached, res) = tokio::task::spawn_blocking(move || {
+            let cached = cached.unwrap_or_else(|| read_cache_file(&package.local_directory));
+            let res = hash_package_files(&package, &cached, &progress);
+            (cached, res)
+        })
+        .await?;
+        let hashes = res?;
+
+        let mut directory = directory.lock().await;
+        let mut cached = directory.take().unwrap_or(cached);
+        let merged = hashes.clone();
+        let cached = tokio::task::spawn_blocking(move || {
+            update_cache(&local_dir, &mut cached, &merged);
+            cached
+        })
+        .await?;
+        *directory = Some(cached);
+
+        *entry = Some(hashes.clone());
+        Ok(hashes)
+    }
+}
+
+fn hash_package_files(
+    package: &DeployPackage,
+    cached: &HashMap<String, FileHash>,
+    progress: &HashProgress,
+) -> anyhow::Result<HashMap<String, FileHash>, anyhow::Error> {
+    let local_dir = &package.local_directory;
+    let files = PackageCreator::collect_package_files(package)?;
+
+    // stat everything first, so progress knows total size
+    let mut hashes: HashMap<String, FileHash> = HashMap::new();
+    let mut pending: Vec<(&String, u64)> = Vec::new();
+    let mut bytes_len = 0;
+    for file in &files {
+        let md = fs::metadata(local_dir.to_string() + file)?;
+        let mtime = md
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        bytes_len += md.len();
+
+        match cached.get(file) {
+            Some(entry) if entry.size == md.len() && entry.mtime == mtime => {
+                hashes.insert(file.to_string(), entry.clone());
+            }
+            _ => pending.push((file, mtime)),
+        }
+    }
+    progress
+        .files_len
+        .store(files.len() as u64, Ordering::Relaxed);
+    progress.bytes_len.store(bytes_len, Ordering::Relaxed);
+    progress.files.store(hashes.len() as u64, Ordering::Relaxed);
+    progress.bytes.store(
+        hashes.values().map(|entry| entry.size).sum(),
+        Ordering::Relaxed,
+    );
+
+    // workers take files one by one, so a few large files don't end up on one thread
+    let workers = thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .min(pending.len());
+    let next = AtomicUsize::new(0);
+    let results: Vec<io::Result<Vec<(String, FileHash)>>> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    while let Some((file, mtime)) =
+                        pending.get(next.fetch_add(1, Ordering::Relaxed))
+                    {
+                        let (size, hash) =
+                            PackageCreator::hash_file(&(local_dir.to_string() + file))?;
+                        progress.files.fetch_add(1, Ordering::Relaxed);
+                        progress.bytes.fetch_add(size, Ordering::Relaxed);
+                        done.push((
+                            file.to_string(),
+                            FileHash {
+                                size,
+                                mtime: *mtime,
+                                hash,
+                            },
+                        ));
+                    }
+                    Ok(done)
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|_| Err(io::Error::other("hashing thread panicked")))
+            })
+            .collect()
+    });
+    for res in results {
+        hashes.extend(res?);
+    }
+
+    Ok(hashes)
+}
+
+/// Merges package hashes into cache of its local directory, entries of other packages
+/// sharing the directory are kept, files deleted locally are dropped.
+fn update_cache(
+    local_dir: &str,
+    cached: &mut HashMap<String, FileHash>,
+    hashes: &HashMap<String, FileHash>,
+) {
+    let mut changed = false;
+    for (file, hash) in hashes {
+        if cached.get(file) != Some(hash) {
+            cached.insert(file.to_string(), hash.clone());
+            changed = true;
+        }
+    }
+    let len = cached.len();
+    cached.retain(|file, _| {
+        hashes.contains_key(file) || fs::metadata(local_dir.to_string() + file).is_ok()
+    });
+    changed |= cached.len() != len;
+
+    if changed {
+        // cache is an optimization only, failing to persist it isn't an error
+        let _ = write_cache_file(local_dir, cached);
+    }
+}
+
+fn read_cache_file(local_dir: &str) -> HashMap<String, FileHash> {
+    let Ok(file) = File::open(Path::new(local_dir).join(HASH_CACHE_FILE)) else {
+        return HashMap::new();
+    };
+    serde_json::from_reader(BufReader::new(file)).unwrap_or_default()
+}
+
+fn write_cache_file(local_dir: &str, cache: &HashMap<String, FileHash>) -> io::Result<()> {
+    let path = Path::new(local_dir).join(HASH_CACHE_FILE);
+    let tmp_path = Path::new(local_dir).join(format!("{}.tmp", HASH_CACHE_FILE));
+    let mut writer = BufWriter::new(File::create(&tmp_path)?);
+    serde_json::to_writer(&mut writer, cache)?;
+    writer.flush()?;
+    fs::rename(tmp_path, path)
+}
@@ -0,0 +1,110 @@
+use super::deployment::open_session;
+use super::remote::run_command;
+use crate::core::constants::VERSION;
+use crate::serialization::{canary::HealthCheck, config::Config, deploy_target::DeployTarget};
+use anyhow::anyhow;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{sleep, timeout};
+
+/// Runs health check against target, retrying it until it passes or retries are spent.
+pub async fn run_health_check(
+    config: &Config,
+    target: &DeployTarget,
+    check: &HealthCheck,
+) -> anyhow::Result<(), anyhow::Error> {
+    let mut attempt = 0;
+    loop {
+        let res = match timeout(
+            Duration::from_secs(check.timeout_seconds()),
+            check_once(config, target, check),
+        )
+        .await
+        {
+            Ok(res) => res,
+            Err(_) => Err(anyhow!("timed out after {}s", check.timeout_seconds())),
+        };
+
+        match res {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt >= check.retries() => return Err(e.context("health check failed")),
+            Err(_) => {
+                attempt += 1;
+                sleep(Duration::from_secs(check.interval_seconds())).await;
+            }
+        }
+    }
+}
+
+async fn check_once(
+    config: &Config,
+    target: &DeployTarget,
+    check: &HealthCheck,
+) -> anyhow::Result<(), anyhow::Error> {
+    if let Some(command) = &check.command {
+        let session = open_session(config, target).await?;
+        let output = run_command(&session, command).await?;
+        if !output.success() {
+            return Err(anyhow!(
+                "`{}` failed with exit code {}: {}",
+                command,
+                output
+                    .exit_status
+                    .map(|status| status.to_string())
+                    .unwrap_or(String::from("unknown")),
+                output.stderr.trim()
+            ));
+        }
+    }
+
+    if let Some(url) = &check.url {
+        probe_url(&url.replace("{host}", &target.host), check.expect_status).await?;
+    }
+
+    Ok(())
+}
+
+/// Minimal plain http GET, healthy when status matches `expect_status` or is 2xx.
+async fn probe_url(url: &str, expect_status: Option<u16>) -> anyhow::Result<(), anyhow::Error> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or(anyhow!("{}: only http:// urls are supported", url))?;
+    let (authority, path) = match rest.find('/') {
+        Some(pos) => (&rest[..pos], &rest[pos..]),
+        None => (rest, "/"),
+    };
+    let address = if authority.contains(':') {
+        authority.to_string()
+    } else {
+        format!("{}:80", authority)
+    };
+
+    let mut stream = TcpStream::connect(&address)
+        .await
+        .map_err(|e| anyhow!("{}: {}", url, e))?;
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: deploy.rs/{}\r\nConnection: close\r\n\r\n",
+        path, authority, VERSION
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+    let response = String::from_utf8_lossy(&response);
+    let status: u16 = response
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse().ok())
+        .ok_or(anyhow!("{}: invalid http response", url))?;
+
+    let healthy = match expect_status {
+        Some(expected) => status == expected,
+        None => (200..300).contains(&status),
+    };
+    if !healthy {
+        return Err(anyhow!("{} responded with status {}", url, status));
+    }
+    Ok(())
+}
@@ -0,0 +1,115 @@
+use super::client::Client;
+use super::hash_cache::FileHash;
+use super::remote::{run_command, run_named_action, shell_quote};
+use crate::core::constants::SUDO_PREPEND;
+use anyhow::anyhow;
+use russh::client::Handle;
+use russh_sftp::client::SftpSession;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use tokio::io::AsyncWriteExt;
+
+/// Deployed files of a package as recorded after the last successful deploy,
+/// lets the next run skip hashing files on target.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Manifest {
+    // set while target is being changed or after rollback, hashes can't be trusted then
+    pub stale: bool,
+    pub files: BTreeMap<String, ManifestEntry>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ManifestEntry {
+    pub size: u64,
+    pub mtime: u64,
+    pub hash: String,
+}
+
+impl Manifest {
+    /// Builds manifest from hashes of local files, which match target once deploy succeeded.
+    pub fn from_hashes(hashes: &HashMap<String, FileHash>) -> Manifest {
+        let files = hashes
+            .iter()
+            .map(|(file, local)| {
+                let entry = ManifestEntry {
+                    size: local.size,
+                    // tar restores modification time on extraction, with seconds precision
+                    mtime: local.mtime / 1_000_000_000,
+                    hash: local.hash.to_string(),
+                };
+                (file.to_string(), entry)
+            })
+            .collect();
+        Manifest {
+            stale: false,
+            files,
+        }
+    }
+}
+
+pub async fn open_sftp(session: &Handle<Client>) -> anyhow::Result<SftpSession, anyhow::Error> {
+    let channel = session.channel_open_session().await?;
+    channel.request_subsystem(true, "sftp").await?;
+    Ok(SftpSession::new(channel.into_stream()).await?)
+}
+
+/// Reads manifest from target, missing or unreadable manifest is reported as `None`.
+pub async fn read_manifest(sftp: &SftpSession, path: &str) -> Option<Manifest> {
+    let data = sftp.read(path).await.ok()?;
+    serde_json::from_slice(&data).ok()
+}
+
+/// Uploads manifest to a temporary file and moves it into place,
+/// so an interrupted upload never leaves a truncated manifest behind.
+pub async fn write_manifest(
+    session: &Handle<Client>,
+    package: &str,
+    path: &str,
+    manifest: &Manifest,
+) -> anyhow::Result<(), anyhow::Error> {
+    let data = serde_json::to_vec(manifest)?;
+
+    // ignore sudo here, file is written over sftp as connected user
+    let mktemp = run_command(session, "mktemp").await?;
+    let tmp_file_name = mktemp.stdout.lines().next().unwrap_or("").to_string();
+    if !mktemp.success() || tmp_file_name.is_empty() {
+        return Err(anyhow!("mktemp failed: {}", mktemp.stderr.trim()));
+    }
+
+    let sftp = open_sftp(session).await?;
+    let mut remote_file = sftp.create(&tmp_file_name).await?;
+    remote_file.write_all(&data).await?;
+    remote_file.shutdown().await?;
+
+    let dir = Path::new(path)
+        .parent()
+        .map(|dir| dir.to_string_lossy().to_string())
+        .unwrap_or_else(|| "/".to_string());
+    let script = format!(
+        "set -e\nmkdir -p {dir}\nmv -f {tmp} {path}\nchmod 644 {path}\n",
+        dir = shell_quote(&dir),
+        tmp = shell_quote(&tmp_file_name),
+        path = shell_quote(path),
+    );
+    let fmt = format!("{}sh -c {}", SUDO_PREPEND, shell_quote(&script));
+    run_named_action(session, package, "write manifest", &fmt).await?;
+    Ok(())
+}
+
+/// Marks manifest on target stale, next run hashes files on target instead of trusting it.
+pub async fn flag_manifest_stale(
+    session: &Handle<Client>,
+    package: &str,
+    path: &str,
+) -> anyhow::Result<(), anyhow::Error> {
+    let sftp = open_sftp(session).await?;
+    let Some(mut manifest) = read_manifest(&sftp, path).await else {
+        return Ok(());
+    };
+    if manifest.stale {
+        return Ok(());
+    }
+    manifest.stale = true;
+    write_manifest(session, package, path, &manifest).await
+}
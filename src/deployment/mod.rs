@@ -1,2 +1,13 @@
+pub mod archive_cache;
+pub mod client;
+#[allow(clippy::module_inception)]
 pub mod deployment;
+pub mod hash_cache;
+pub mod health_check;
+pub mod manifest;
 pub mod packaging;
+pub mod plan;
+pub mod remote;
+pub mod retry;
+pub mod rollback;
+pub mod summary;
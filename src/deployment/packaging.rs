@@ -1,12 +1,34 @@
+use super::hash_cache::FileHash;
+use crate::serialization::{
+    compression::{Compression, CompressionCodec},
+    deploy_package::DeployPackage,
+    package_filter::PackageFilter,
+};
 use core::fmt::Write;
-use flate2::{write::GzEncoder, Compression};
+use flate2::write::GzEncoder;
+use serde::Serialize;
 use sha1::{Digest, Sha1};
 use std::{
     collections::HashMap,
     fs::{self, metadata, File},
-    io,
-    path::{Path, PathBuf},
+    io::{self, Read},
+    path::Path,
 };
+use xz2::write::XzEncoder;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileChangeKind {
+    New,
+    Modified,
+}
+
+#[derive(Clone, Serialize)]
+pub struct FileChange {
+    pub path: String,
+    pub size: u64,
+    pub kind: FileChangeKind,
+}
 
 pub struct PackageCreator<'a> {
     server_hash_map: &'a HashMap<String, String>,
@@ -19,119 +41,155 @@ impl PackageCreator<'_> {
         }
     }
 
-    pub fn prepare_package_for_target(self, local_temp_file: &File, local_dir: String) -> bool {
-        let mut target_files: Vec<String> = Vec::new();
+    /// Lists local files which are missing on target or differ from it.
+    pub fn compute_changes(&self, local_hashes: &HashMap<String, FileHash>) -> Vec<FileChange> {
+        let mut changes: Vec<FileChange> = Vec::new();
         for (key, val) in self.server_hash_map {
-            // get hash
-            let path = local_dir.clone() + key;
-            let mut file = fs::File::open(&path).unwrap();
-
-            let mut hasher = Sha1::new();
-            io::copy(&mut file, &mut hasher).unwrap();
-            let hash_bytes = hasher.finalize();
-            let n = hash_bytes.len();
-            let mut s = String::with_capacity(2 * n);
-            for byte in hash_bytes {
-                write!(s, "{:02X}", byte).unwrap();
-            }
-            s = s.to_lowercase();
-
-            if &s == val {
+            let Some(local) = local_hashes.get(key) else {
+                continue;
+            };
+            if &local.hash == val {
                 continue;
             }
 
-            target_files.push(key.to_string());
-        }
-
-        if target_files.len() == 0 {
-            return false;
+            changes.push(FileChange {
+                path: key.to_string(),
+                size: local.size,
+                kind: if val.is_empty() {
+                    FileChangeKind::New
+                } else {
+                    FileChangeKind::Modified
+                },
+            });
         }
 
-        //let tar_gz: File = tempfile::NamedTempFile::new().unwrap(); // tempfile::tempfile().unwrap();
-        //let tar_gz: File = File::create("D:/test.tar.gz").unwrap();
-        //let file_handle = tar_gz.try_clone().unwrap();
+        changes.sort_by(|a, b| a.path.cmp(&b.path));
+        changes
+    }
 
-        let enc = GzEncoder::new(local_temp_file, Compression::default());
-        let mut tar = tar::Builder::new(enc);
+    /// Returns size and lowercase hex sha1 of the file.
+    pub fn hash_file(path: &str) -> io::Result<(u64, String)> {
+        Self::hash_reader(fs::File::open(path)?)
+    }
 
-        for key in target_files {
-            //let key_clone = key.clone();
-            let res = tar.append_path_with_name(local_dir.clone() + &key, key);
-            match res {
-                Ok(()) => {
-                    //println!("Ok, added {}", key_clone)
-                },
-                Err(_e) => {
-                    //println!("Failed to add {} because {}", key_clone, e)
-                }
-            }
-            //tar.append_file(key, &mut file).unwrap();
+    /// Hasher fed with first `len` bytes of file, fails when file is shorter.
+    pub fn hash_file_prefix(path: &Path, len: u64) -> io::Result<Sha1> {
+        let mut file = fs::File::open(path)?.take(len);
+        let mut hasher = Sha1::new();
+        if io::copy(&mut file, &mut hasher)? != len {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
         }
+        Ok(hasher)
+    }
 
-        tar.finish().unwrap();
-
-        true
+    fn hash_reader(mut reader: impl Read) -> io::Result<(u64, String)> {
+        let mut hasher = Sha1::new();
+        let size = io::copy(&mut reader, &mut hasher)?;
+        Ok((size, Self::hex_digest(hasher)))
     }
 
-    // static block
-    pub fn collect_files_ext(local_dir: String, files: &mut Vec<String>) {
-        let ldir = PathBuf::from(local_dir);
-        PackageCreator::collect_files(
-            ldir.canonicalize().unwrap().to_str().unwrap().to_string(),
-            "".to_string(),
-            files,
-        );
+    /// Lowercase hex digest, same as printed by `sha1sum`.
+    pub fn hex_digest(hasher: Sha1) -> String {
+        let hash_bytes = hasher.finalize();
+        let n = hash_bytes.len();
+        let mut s = String::with_capacity(2 * n);
+        for byte in hash_bytes {
+            write!(s, "{:02x}", byte).unwrap();
+        }
+        s
     }
 
-    pub fn collect_files(local_dir: String, cur_dir: String, files: &mut Vec<String>) {
-        let path_to_current_directory = Path::new(&local_dir).join(cur_dir);
-        let paths: Vec<_> = fs::read_dir(path_to_current_directory)
-            .unwrap()
-            .map(|r| r.unwrap())
-            .collect();
-
-        for path in &paths {
-            let file_path = path.path();
-            let md = metadata(file_path).unwrap();
-            if !md.is_file() {
-                continue;
+    /// Packs listed files of `local_dir` into tar archive compressed with selected codec.
+    pub fn build_archive(
+        local_temp_file: &File,
+        local_dir: &str,
+        target_files: &[String],
+        compression: &Compression,
+    ) -> io::Result<()> {
+        let level = compression.level();
+        match compression.codec {
+            CompressionCodec::Gzip => {
+                let enc = GzEncoder::new(local_temp_file, flate2::Compression::new(level));
+                PackageCreator::append_files(enc, local_dir, target_files)?.finish()?;
             }
-
-            let file_path = path.path();
-            let mut name = file_path
-                .into_os_string()
-                .into_string()
-                .unwrap()
-                .replace(&local_dir, "");
-
-            // windows
-            if name.starts_with('\\') {
-                name = name[1..].to_string();
+            CompressionCodec::Zstd => {
+                let enc = zstd::Encoder::new(local_temp_file, level as i32)?;
+                PackageCreator::append_files(enc, local_dir, target_files)?.finish()?;
             }
-
-            // unix
-            if name.starts_with('/') {
-                name = name[1..].to_string();
+            CompressionCodec::Xz => {
+                let enc = XzEncoder::new(local_temp_file, level);
+                PackageCreator::append_files(enc, local_dir, target_files)?.finish()?;
+            }
+            CompressionCodec::None => {
+                PackageCreator::append_files(local_temp_file, local_dir, target_files)?;
             }
+        }
+        Ok(())
+    }
 
-            name = name.replace("\\", "/");
+    fn append_files<W: io::Write>(
+        writer: W,
+        local_dir: &str,
+        target_files: &[String],
+    ) -> io::Result<W> {
+        let mut tar = tar::Builder::new(writer);
 
-            files.push(name);
+        // a file missing from archive would still be recorded in manifest as deployed
+        for key in target_files {
+            tar.append_path_with_name(local_dir.to_string() + key, key)
+                .map_err(|e| io::Error::new(e.kind(), format!("can't pack {}: {}", key, e)))?;
         }
 
-        for path in paths {
-            let file_path = path.path();
-            let md = metadata(file_path).unwrap();
-            if !md.is_dir() {
-                continue;
-            }
+        tar.into_inner()
+    }
 
-            let file_path = path.path();
-            PackageCreator::collect_files(
-                local_dir.to_string(),
-                file_path.to_str().unwrap().to_string(),
-                files,
-            );
+    // static block
+    /// Collects files of the package, excluded directories aren't walked at all.
+    pub fn collect_package_files(package: &DeployPackage) -> Result<Vec<String>, ignore::Error> {
+        let filter = PackageFilter::new(package)?;
+        let mut files: Vec<String> = Vec::new();
+        PackageCreator::collect_files(
+            Path::new(&package.local_directory),
+            "",
+            &filter,
+            &mut files,
+        )?;
+        Ok(files)
+    }
+
+    fn collect_files(
+        local_dir: &Path,
+        cur_dir: &str,
+        filter: &PackageFilter,
+        files: &mut Vec<String>,
+    ) -> io::Result<()> {
+        for entry in fs::read_dir(local_dir.join(cur_dir))? {
+            let entry = entry?;
+            let file_name = entry.file_name().into_string().map_err(|name| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("file name {:?} is not valid UTF-8", name),
+                )
+            })?;
+            let name = match cur_dir.is_empty() {
+                true => file_name,
+                false => format!("{}/{}", cur_dir, file_name),
+            };
+
+            // symlinks are followed, dangling ones have nothing to deploy
+            let md = match metadata(entry.path()) {
+                Ok(md) => md,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            if md.is_dir() {
+                if !filter.is_dir_excluded(&name) {
+                    PackageCreator::collect_files(local_dir, &name, filter, files)?;
+                }
+            } else if md.is_file() && filter.is_included(&name) {
+                files.push(name);
+            }
         }
+        Ok(())
     }
 }
@@ -0,0 +1,31 @@
+use super::packaging::FileChange;
+use super::summary::TargetResult;
+use serde::Serialize;
+
+#[derive(Serialize)]
+pub struct PackagePlan {
+    pub package: String,
+    pub target_directory: String,
+    pub changes: Vec<FileChange>,
+    // files removed from target in mirror mode
+    pub deletions: Vec<String>,
+    pub pre_deploy_actions: Vec<String>,
+    pub post_deploy_actions: Vec<String>,
+}
+
+impl PackagePlan {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty() && self.deletions.is_empty()
+    }
+
+    pub fn total_size(&self) -> u64 {
+        self.changes.iter().map(|change| change.size).sum()
+    }
+}
+
+#[derive(Serialize)]
+pub struct TargetPlan {
+    #[serde(flatten)]
+    pub result: TargetResult,
+    pub packages: Vec<PackagePlan>,
+}
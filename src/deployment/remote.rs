@@ -0,0 +1,242 @@
+use super::client::Client;
+use anyhow::anyhow;
+use russh::client::Handle;
+use std::{collections::HashMap, fmt};
+
+pub struct CommandOutput {
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_status: Option<u32>,
+}
+
+impl CommandOutput {
+    pub fn success(&self) -> bool {
+        self.exit_status == Some(0)
+    }
+}
+
+/// Executes command in a new channel and collects its output.
+pub async fn run_command(
+    session: &Handle<Client>,
+    command: &str,
+) -> anyhow::Result<CommandOutput, anyhow::Error> {
+    let mut channel = session.channel_open_session().await?;
+    channel.exec(true, command).await?;
+
+    let mut stdout: Vec<u8> = Vec::new();
+    let mut stderr: Vec<u8> = Vec::new();
+    let mut exit_status = None;
+    while let Some(res) = channel.wait().await {
+        match res {
+            russh::ChannelMsg::Data { ref data } => {
+                stdout.extend_from_slice(data);
+            }
+            russh::ChannelMsg::ExtendedData { ref data, ext: 1 } => {
+                stderr.extend_from_slice(data);
+            }
+            russh::ChannelMsg::ExitStatus {
+                exit_status: status,
+            } => {
+                exit_status = Some(status);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(CommandOutput {
+        stdout: String::from_utf8_lossy(&stdout).to_string(),
+        stderr: String::from_utf8_lossy(&stderr).to_string(),
+        exit_status,
+    })
+}
+
+/// Remote step which completed with a non-zero exit status.
+/// Not retried, running the same command again is unlikely to help.
+#[derive(Debug)]
+pub struct ActionError {
+    pub package: String,
+    pub action: String,
+    pub exit_status: u32,
+    pub stderr: String,
+}
+
+impl fmt::Display for ActionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: `{}` failed with exit code {}",
+            self.package, self.action, self.exit_status
+        )?;
+        if !self.stderr.is_empty() {
+            write!(f, ": {}", self.stderr)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ActionError {}
+
+/// Runs command and turns a non-zero exit status into `ActionError`.
+pub async fn run_action(
+    session: &Handle<Client>,
+    package: &str,
+    command: &str,
+) -> anyhow::Result<CommandOutput, anyhow::Error> {
+    run_named_action(session, package, command, command).await
+}
+
+/// Same as `run_action`, but reports failure under `name` instead of the whole command,
+/// used for generated scripts.
+pub async fn run_named_action(
+    session: &Handle<Client>,
+    package: &str,
+    name: &str,
+    command: &str,
+) -> anyhow::Result<CommandOutput, anyhow::Error> {
+    let output = run_command(session, command).await?;
+    match output.exit_status {
+        Some(0) => Ok(output),
+        Some(exit_status) => Err(ActionError {
+            package: package.to_string(),
+            action: name.to_string(),
+            exit_status,
+            stderr: output.stderr.trim().to_string(),
+        }
+        .into()),
+        // channel closed without reporting status, most likely connection dropped
+        None => Err(anyhow!("connection lost while running `{}`", name)),
+    }
+}
+
+/// Hashes file on target, only its first `len` bytes when set.
+/// Runs without sudo, meant for temporary files of connected user.
+pub async fn remote_sha1(
+    session: &Handle<Client>,
+    path: &str,
+    len: Option<u64>,
+) -> anyhow::Result<String, anyhow::Error> {
+    let fmt = match len {
+        Some(len) => format!("head -c {} {} | sha1sum", len, shell_quote(path)),
+        None => format!("sha1sum {}", shell_quote(path)),
+    };
+    let output = run_command(session, &fmt).await?;
+    let hash = output.stdout.split_whitespace().next().unwrap_or("");
+    if !output.success() || hash.is_empty() {
+        return Err(anyhow!(
+            "sha1sum of {} failed: {}",
+            path,
+            output.stderr.trim()
+        ));
+    }
+    Ok(hash.to_string())
+}
+
+/// Quotes value for POSIX shell.
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Parses `sha1sum` output into path -> checksum map.
+/// Names containing backslash or newline are escaped by sha1sum and marked with leading `\`.
+pub fn parse_sha1sum_output(output: &str) -> HashMap<String, String> {
+    let mut checksums = HashMap::new();
+    for line in output.lines() {
+        let (escaped, line) = match line.strip_prefix('\\') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        // "<hash>  <name>" in text mode, "<hash> *<name>" in binary mode
+        let Some((hash, name)) = line.split_once(' ') else {
+            continue;
+        };
+        let name = name
+            .strip_prefix(' ')
+            .or_else(|| name.strip_prefix('*'))
+            .unwrap_or(name);
+
+        let name = if escaped {
+            unescape_sha1sum_name(name)
+        } else {
+            name.to_string()
+        };
+        checksums.insert(name, hash.to_lowercase());
+    }
+    checksums
+}
+
+fn unescape_sha1sum_name(name: &str) -> String {
+    let mut unescaped = String::with_capacity(name.len());
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_sha1sum_output;
+
+    #[test]
+    fn parses_text_mode() {
+        let checksums =
+            parse_sha1sum_output("84a516841ba77a5b4648de2cd0dfcb30ea46dbb4  /tmp/sh1/plain.txt\n");
+        assert_eq!(checksums.len(), 1);
+        assert_eq!(
+            checksums["/tmp/sh1/plain.txt"],
+            "84a516841ba77a5b4648de2cd0dfcb30ea46dbb4"
+        );
+    }
+
+    #[test]
+    fn parses_binary_mode() {
+        let checksums =
+            parse_sha1sum_output("84A516841BA77A5B4648DE2CD0DFCB30EA46DBB4 */tmp/sh1/plain.txt\n");
+        assert_eq!(
+            checksums["/tmp/sh1/plain.txt"],
+            "84a516841ba77a5b4648de2cd0dfcb30ea46dbb4"
+        );
+    }
+
+    #[test]
+    fn keeps_spaces_in_names() {
+        let checksums =
+            parse_sha1sum_output("84a516841ba77a5b4648de2cd0dfcb30ea46dbb4   leading space.txt\n");
+        assert!(checksums.contains_key(" leading space.txt"));
+    }
+
+    #[test]
+    fn unescapes_backslash_and_newline_names() {
+        let checksums = parse_sha1sum_output(concat!(
+            "\\86f7e437faa5a7fce15d1ddcb9eaeaea377667b8  /tmp/sh1/we\\\\ird\n",
+            "\\e9d71f5ee7c92d6dc9e92ffdad17b8bd49418f98  /tmp/sh1/new\\nline\n",
+        ));
+        assert_eq!(
+            checksums["/tmp/sh1/we\\ird"],
+            "86f7e437faa5a7fce15d1ddcb9eaeaea377667b8"
+        );
+        assert_eq!(
+            checksums["/tmp/sh1/new\nline"],
+            "e9d71f5ee7c92d6dc9e92ffdad17b8bd49418f98"
+        );
+    }
+
+    #[test]
+    fn missing_files_have_no_checksum() {
+        // sha1sum reports missing files on stderr only
+        assert!(parse_sha1sum_output("").is_empty());
+
+        let checksums =
+            parse_sha1sum_output("84a516841ba77a5b4648de2cd0dfcb30ea46dbb4  /tmp/sh1/plain.txt\n");
+        assert!(!checksums.contains_key("/tmp/sh1/missing.txt"));
+    }
+}
@@ -0,0 +1,40 @@
+use crate::serialization::retry_policy::RetryPolicy;
+use std::time::Duration;
+use tokio::time::sleep;
+
+pub struct Retry {
+    policy: RetryPolicy,
+    attempt: u32,
+    delay_ms: f64,
+}
+
+impl Retry {
+    pub fn new(policy: RetryPolicy) -> Retry {
+        Retry {
+            policy,
+            attempt: 0,
+            delay_ms: policy.initial_delay_ms() as f64,
+        }
+    }
+
+    /// Registers a failed attempt and waits before the next one.
+    /// Returns the last error once all attempts are spent.
+    pub async fn backoff(&mut self, err: anyhow::Error) -> anyhow::Result<()> {
+        self.attempt += 1;
+        if self.attempt >= self.policy.max_attempts() {
+            return Err(err.context(format!("giving up after {} attempts", self.attempt)));
+        }
+
+        let jitter = self.policy.jitter();
+        let factor = if jitter > 0.0 {
+            rand::random_range(1.0 - jitter..=1.0 + jitter)
+        } else {
+            1.0
+        };
+        sleep(Duration::from_millis((self.delay_ms * factor) as u64)).await;
+
+        self.delay_ms =
+            (self.delay_ms * self.policy.backoff_factor()).min(self.policy.max_delay_ms() as f64);
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+use super::client::Client;
+use super::deployment::open_session;
+use super::manifest::flag_manifest_stale;
+use super::remote::{run_action, run_named_action, shell_quote, ActionError};
+use crate::core::constants::SUDO_PREPEND;
+use crate::serialization::{
+    config::Config, deploy_package::DeployPackage, deploy_target::DeployTarget,
+};
+use anyhow::anyhow;
+use russh::client::Handle;
+
+/// Restores previous state of every package of the target, returns restored state per package.
+pub async fn rollback(
+    config: &Config,
+    target: &DeployTarget,
+    to: Option<&str>,
+) -> anyhow::Result<Vec<(String, String)>, anyhow::Error> {
+    // every package is checked before any of them is touched
+    let mut packages = Vec::new();
+    for package in &target.packages {
+        let Some(package_element) = config.packages.get(package) else {
+            return Err(anyhow!("package {} is not defined", package));
+        };
+        if package_element.releases.is_none() && !package_element.backup.unwrap_or(false) {
+            return Err(anyhow!(
+                "{}: nothing to roll back to, package doesn't keep releases or backups",
+                package
+            ));
+        }
+        packages.push((package, package_element));
+    }
+
+    let session = open_session(config, target).await?;
+
+    let mut restored = Vec::new();
+    for (package, package_element) in packages {
+        // 1. restore files, manifest no longer describes them
+        flag_manifest_stale(&session, package, &package_element.manifest_file(package)).await?;
+        let state = match package_element.releases {
+            Some(_) => rollback_release(&session, package, package_element, to).await?,
+            None => rollback_backup(&session, package, package_element, to).await?,
+        };
+
+        // 2. rerun post deploy actions against restored files
+        run_post_deploy_actions(&session, package, package_element).await?;
+
+        restored.push((package.to_string(), state));
+    }
+
+    Ok(restored)
+}
+
+/// Switches `current` symlink to the release before the current one or to `to`.
+async fn rollback_release(
+    session: &Handle<Client>,
+    package: &str,
+    package_element: &DeployPackage,
+    to: Option<&str>,
+) -> anyhow::Result<String, anyhow::Error> {
+    let base = shell_quote(&package_element.target_directory);
+    let fmt = format!("cd {} && readlink current && ls -1 releases", base);
+    let output = run_action(session, package, &fmt).await?;
+    let mut lines = output.stdout.lines();
+    let current = lines
+        .next()
+        .and_then(|link| link.trim().strip_prefix("releases/"))
+        .unwrap_or("")
+        .to_string();
+    let mut releases: Vec<&str> = lines.map(str::trim).filter(|l| !l.is_empty()).collect();
+    releases.sort();
+
+    let release = match to {
+        Some(to) if releases.contains(&to) => to.to_string(),
+        Some(to) => return Err(anyhow!("{}: release {} not found", package, to)),
+        None => releases
+            .iter()
+            .rev()
+            .find(|release| **release < current.as_str())
+            .map(|release| release.to_string())
+            .ok_or(anyhow!("{}: no release before {}", package, current))?,
+    };
+
+    let script = format!(
+        "set -e\ncd {base}\nln -sfn {release} current.tmp\nmv -Tf current.tmp current\n",
+        base = base,
+        release = shell_quote(&format!("releases/{}", release)),
+    );
+    let fmt = format!("{}sh -c {}", SUDO_PREPEND, shell_quote(&script));
+    run_named_action(session, package, &format!("switch to {}", release), &fmt).await?;
+
+    Ok(format!("release {}", release))
+}
+
+/// Undoes deploys recorded in backup journal, the last one or every deploy after `to`,
+/// newest first: created files are removed and overwritten ones extracted from backup.
+/// Like with releases, target is left as it was right after deploy `to`.
+async fn rollback_backup(
+    session: &Handle<Client>,
+    package: &str,
+    package_element: &DeployPackage,
+    to: Option<&str>,
+) -> anyhow::Result<String, anyhow::Error> {
+    let dir = shell_quote(&package_element.backup_directory(package));
+    let fmt = format!("{}cat {}/journal", SUDO_PREPEND, dir);
+    let output = run_action(session, package, &fmt)
+        .await
+        .map_err(|e| e.context(format!("{}: no backups found", package)))?;
+    let journal: Vec<&str> = output
+        .stdout
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect();
+
+    let undo: Vec<&str> = match to {
+        Some(to) => match journal.iter().position(|id| *id == to) {
+            Some(pos) if pos + 1 == journal.len() => {
+                return Err(anyhow!("{}: {} is the latest deploy", package, to))
+            }
+            Some(pos) => journal[pos + 1..].to_vec(),
+            None => return Err(anyhow!("{}: backup {} not found", package, to)),
+        },
+        None => journal.last().copied().into_iter().collect(),
+    };
+    if undo.is_empty() {
+        return Err(anyhow!("{}: no backups found", package));
+    }
+
+    for id in undo.iter().rev() {
+        let script = format!(
+            "set -e\n\
+             cd {target}\n\
+             while IFS= read -r file; do rm -f -- \"$file\"; done < {dir}/{id}.new\n\
+             tar -xzf {dir}/{id}.tar.gz\n\
+             cd {dir}\n\
+             grep -vx {id} journal > journal.tmp || true\n\
+             mv journal.tmp journal\n\
+             rm -f {id}.tar.gz {id}.modified {id}.new\n",
+            target = shell_quote(&package_element.target_directory),
+            dir = dir,
+            id = shell_quote(id),
+        );
+        let fmt = format!("{}sh -c {}", SUDO_PREPEND, shell_quote(&script));
+        run_named_action(session, package, &format!("restore backup {}", id), &fmt).await?;
+    }
+
+    Ok(format!("state before {}", undo[0]))
+}
+
+async fn run_post_deploy_actions(
+    session: &Handle<Client>,
+    package: &str,
+    package_element: &DeployPackage,
+) -> anyhow::Result<(), anyhow::Error> {
+    for action in package_element.post_deploy_actions.iter().flatten() {
+        if let Err(e) = run_action(session, package, action.command()).await {
+            if !(e.is::<ActionError>() && action.continue_on_error()) {
+                return Err(e);
+            }
+        }
+    }
+    Ok(())
+}
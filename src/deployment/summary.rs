@@ -0,0 +1,81 @@
+use super::client::ClientError;
+use crate::core::constants::{EXIT_AUTH_FAILURE, EXIT_FAILURE, EXIT_PARTIAL_FAILURE, EXIT_SUCCESS};
+use serde::Serialize;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TargetOutcome {
+    Succeeded,
+    Failed,
+    AuthFailed,
+}
+
+#[derive(Serialize)]
+pub struct TargetResult {
+    pub name: String,
+    pub host: String,
+    pub outcome: TargetOutcome,
+    pub error: Option<String>,
+}
+
+impl TargetResult {
+    pub fn succeeded(name: String, host: String) -> TargetResult {
+        TargetResult {
+            name,
+            host,
+            outcome: TargetOutcome::Succeeded,
+            error: None,
+        }
+    }
+
+    pub fn failed(name: String, host: String, err: &anyhow::Error) -> TargetResult {
+        // authentication problems (including rejected host keys) are reported separately,
+        // they usually mean credentials or known_hosts need attention
+        let outcome = match err.downcast_ref::<ClientError>() {
+            Some(ClientError::AuthenticationFailed(_)) | Some(ClientError::HostKeyRejected(_)) => {
+                TargetOutcome::AuthFailed
+            }
+            _ => TargetOutcome::Failed,
+        };
+        TargetResult {
+            name,
+            host,
+            outcome,
+            error: Some(format!("{:#}", err)),
+        }
+    }
+}
+
+pub struct DeploymentSummary {
+    pub targets: Vec<TargetResult>,
+}
+
+impl DeploymentSummary {
+    pub fn succeeded_count(&self) -> u32 {
+        self.targets
+            .iter()
+            .filter(|t| t.outcome == TargetOutcome::Succeeded)
+            .count() as u32
+    }
+
+    pub fn failed_count(&self) -> u32 {
+        self.targets.len() as u32 - self.succeeded_count()
+    }
+
+    pub fn exit_code(&self) -> u8 {
+        let failed = self.failed_count();
+        if failed == 0 {
+            EXIT_SUCCESS
+        } else if self
+            .targets
+            .iter()
+            .any(|t| t.outcome == TargetOutcome::AuthFailed)
+        {
+            EXIT_AUTH_FAILURE
+        } else if failed as usize == self.targets.len() {
+            EXIT_FAILURE
+        } else {
+            EXIT_PARTIAL_FAILURE
+        }
+    }
+}
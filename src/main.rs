@@ -1,3 +1,4 @@
+use clap::Parser;
 use core::time::Duration;
 use crossterm::{
     event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
@@ -5,6 +6,11 @@ use crossterm::{
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use deploy::{
+    cli::{
+        args::{normalize_args, Cli, Command, OutputFormat},
+        commands,
+    },
+    core::constants::{EXIT_CANCELLED, EXIT_CONFIG_ERROR, EXIT_FAILURE},
     deployment::deployment,
     serialization::config::Config,
     states::{
@@ -20,39 +26,84 @@ use std::{io, sync::Arc};
 
 #[tokio::main]
 async fn main() -> ExitCode {
-    // load deploy configuration
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        println!("Error! No config specified.");
-        return ExitCode::from(1);
-    }
+    let cli = Cli::parse_from(normalize_args(env::args().collect()));
 
-    if !std::path::Path::new(&args[1]).exists() {
-        println!("Error! Config {} doesn't exists", &args[1]);
-        return ExitCode::from(2);
-    }
+    let code = match cli.command {
+        Command::Deploy(args) => {
+            let output = args.output;
+            match commands::load_config(&args.config.config, &args.select, true) {
+                Ok(mut config) => {
+                    config.verify = args.verify;
+                    match args.dry_run {
+                        true => run_plan(config, output).await,
+                        false => run_deploy(config, args.exit_on_finish, output).await,
+                    }
+                }
+                Err(e) => config_error(e),
+            }
+        }
+        Command::Plan(args) => match commands::load_config(&args.config.config, &args.select, true)
+        {
+            Ok(mut config) => {
+                config.verify = args.verify;
+                run_plan(config, args.output).await
+            }
+            Err(e) => config_error(e),
+        },
+        Command::Validate(args) => commands::run_validate(&args.config),
+        Command::Exec(args) => {
+            match commands::load_config(&args.config.config, &args.select, false) {
+                Ok(config) => commands::run_exec(config, &args.command, args.yes).await,
+                Err(e) => config_error(e),
+            }
+        }
+        Command::Rollback(args) => {
+            match commands::load_config(&args.config.config, &args.select, false) {
+                Ok(config) => commands::run_rollback(config, args.to, args.yes).await,
+                Err(e) => config_error(e),
+            }
+        }
+        Command::Status(args) => {
+            match commands::load_config(&args.config.config, &args.select, false) {
+                Ok(config) => commands::run_status(config).await,
+                Err(e) => config_error(e),
+            }
+        }
+    };
+    ExitCode::from(code)
+}
 
-    let config = Arc::new(Mutex::new(Config::read_config(&args[1]).unwrap()));
-    //println!("test {}", config.read().unwrap().use_sudo.unwrap_or(false));
+fn config_error(e: String) -> u8 {
+    // stderr, so `--output json` stdout stays one event per line
+    eprintln!("Error! {}", e);
+    EXIT_CONFIG_ERROR
+}
 
-    // create states
-    let mut exit_on_finish = false;
-    if args.len() > 2 {
-        exit_on_finish = args[2].parse().unwrap_or(false);
-    }
+fn create_ui_state(
+    config: &Config,
+    exit_on_finish: bool,
+    json_output: bool,
+) -> Arc<Mutex<UIStore>> {
+    Arc::new(Mutex::new(
+        ui_state::UIStore::new()
+            .set_targets_count(config.targets.len() as u32)
+            .set_packages_count(config.packages.len() as u32)
+            .set_deployed_count(0)
+            .set_exit_on_finish(exit_on_finish)
+            .set_json_output(json_output)
+            .finalize(),
+    ))
+}
 
-    let ui_state: Arc<Mutex<UIStore>>;
-    {
-        let config_locked = config.lock().await;
-        ui_state = Arc::new(Mutex::new(
-            ui_state::UIStore::new()
-                .set_targets_count(config_locked.targets.len() as u32)
-                .set_packages_count(config_locked.packages.len() as u32)
-                .set_deployed_count(0)
-                .set_exit_on_finish(exit_on_finish)
-                .finalize(),
-        ));
-    }
+async fn run_plan(config: Config, output: OutputFormat) -> u8 {
+    let ui_state = create_ui_state(&config, false, false);
+    commands::run_plan(Arc::new(Mutex::new(config)), ui_state, output).await
+}
+
+async fn run_deploy(config: Config, exit_on_finish: bool, output: OutputFormat) -> u8 {
+    let json_output = output == OutputFormat::Json;
+    let ui_state = create_ui_state(&config, exit_on_finish, json_output);
+    let config = Arc::new(Mutex::new(config));
 
     // run deployment thread
     {
@@ -62,21 +113,70 @@ async fn main() -> ExitCode {
 
     let handle = tokio::spawn(deployment::begin_deployment(config, ui_state.clone()));
 
+    // json output replaces ui, events are printed to stdout as they happen
+    if json_output {
+        let res = tokio::select! {
+            res = handle => res,
+            _ = tokio::signal::ctrl_c() => {
+                eprintln!("Deployment cancelled.");
+                return EXIT_CANCELLED;
+            }
+        };
+        return match res {
+            Ok(Ok(summary)) => summary.exit_code(),
+            Ok(Err(e)) => {
+                eprintln!("Deployment failed: {:#}", e);
+                EXIT_FAILURE
+            }
+            Err(e) => {
+                eprintln!("Deployment crashed: {}", e);
+                EXIT_FAILURE
+            }
+        };
+    }
+
     // create cool UI
     let term_res = setup_terminal();
-    match term_res {
+    let res = match term_res {
         Ok(mut terminal) => {
             let _ = run(&mut terminal, ui_state.clone()).await;
             let _ = restore_terminal(&mut terminal);
+
+            // leaving ui before deployment finished cancels it
+            let finished = matches!(ui_state.lock().await.screen, UIScreen::FINISHED_END);
+            if !finished {
+                handle.abort();
+                println!("Deployment cancelled.");
+                return EXIT_CANCELLED;
+            }
+            handle.await
         }
         _ => {
             println!("Can't create ui, performing silent deployment.");
-            let _res = handle.await;
-            println!("Deployment complete.");
+            tokio::select! {
+                res = handle => res,
+                _ = tokio::signal::ctrl_c() => {
+                    println!("Deployment cancelled.");
+                    return EXIT_CANCELLED;
+                }
+            }
         }
-    }
+    };
 
-    ExitCode::from(0)
+    match res {
+        Ok(Ok(summary)) => {
+            commands::print_summary(&summary);
+            summary.exit_code()
+        }
+        Ok(Err(e)) => {
+            println!("Deployment failed: {:#}", e);
+            EXIT_FAILURE
+        }
+        Err(e) => {
+            println!("Deployment crashed: {}", e);
+            EXIT_FAILURE
+        }
+    }
 }
 
 fn setup_terminal() -> Result<Terminal<CrosstermBackend<Stdout>>, Box<dyn Error>> {
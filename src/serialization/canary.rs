@@ -0,0 +1,57 @@
+use super::deploy_target::DeployTarget;
+use crate::core::constants::{
+    HEALTH_CHECK_INTERVAL_SECONDS, HEALTH_CHECK_RETRIES, HEALTH_CHECK_TIMEOUT_SECONDS,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct HealthCheck {
+    // executed on the canary target, exit code 0 means healthy
+    pub command: Option<String>,
+    // probed from the deploying machine, `{host}` is replaced with target host
+    pub url: Option<String>,
+    #[serde(rename = "expectStatus")]
+    pub expect_status: Option<u16>,
+    #[serde(rename = "timeoutSeconds")]
+    pub timeout_seconds: Option<u64>,
+    pub retries: Option<u32>,
+    #[serde(rename = "intervalSeconds")]
+    pub interval_seconds: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct Canary {
+    // target names or tags deployed before the rest of the fleet
+    pub targets: Vec<String>,
+    #[serde(rename = "healthCheck")]
+    pub health_check: Option<HealthCheck>,
+    #[serde(rename = "soakSeconds")]
+    pub soak_seconds: Option<u64>,
+}
+
+impl HealthCheck {
+    pub fn timeout_seconds(&self) -> u64 {
+        self.timeout_seconds.unwrap_or(HEALTH_CHECK_TIMEOUT_SECONDS)
+    }
+
+    pub fn retries(&self) -> u32 {
+        self.retries.unwrap_or(HEALTH_CHECK_RETRIES)
+    }
+
+    pub fn interval_seconds(&self) -> u64 {
+        self.interval_seconds
+            .unwrap_or(HEALTH_CHECK_INTERVAL_SECONDS)
+    }
+}
+
+impl Canary {
+    pub fn is_canary(&self, target: &DeployTarget) -> bool {
+        self.targets
+            .iter()
+            .any(|entry| target.matches_name_or_tag(entry))
+    }
+
+    pub fn soak_seconds(&self) -> u64 {
+        self.soak_seconds.unwrap_or(0)
+    }
+}
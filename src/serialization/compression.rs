@@ -0,0 +1,93 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum CompressionCodec {
+    #[default]
+    Gzip,
+    Zstd,
+    Xz,
+    // plain tar, for packages of already compressed media
+    None,
+}
+
+impl fmt::Display for CompressionCodec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            CompressionCodec::Gzip => "gzip",
+            CompressionCodec::Zstd => "zstd",
+            CompressionCodec::Xz => "xz",
+            CompressionCodec::None => "none",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, Debug)]
+pub struct Compression {
+    pub codec: CompressionCodec,
+    pub level: Option<u32>,
+}
+
+impl Compression {
+    pub fn level(&self) -> u32 {
+        self.level.unwrap_or(match self.codec {
+            CompressionCodec::Gzip | CompressionCodec::Xz => 6,
+            CompressionCodec::Zstd => 3,
+            CompressionCodec::None => 0,
+        })
+    }
+
+    /// Reports level outside of codec's range.
+    pub fn validate(&self) -> Option<String> {
+        let level = self.level?;
+        let (min, max) = match self.codec {
+            CompressionCodec::Gzip | CompressionCodec::Xz => (0, 9),
+            CompressionCodec::Zstd => (1, 22),
+            CompressionCodec::None => {
+                return Some("compression level can't be set for codec none".to_string())
+            }
+        };
+        if !(min..=max).contains(&level) {
+            return Some(format!(
+                "compression level {} is out of range {}-{} for {}",
+                level, min, max, self.codec
+            ));
+        }
+        None
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self.codec {
+            CompressionCodec::Gzip => "tar.gz",
+            CompressionCodec::Zstd => "tar.zst",
+            CompressionCodec::Xz => "tar.xz",
+            CompressionCodec::None => "tar",
+        }
+    }
+
+    /// Arguments of remote `tar` extracting archive named by the following argument.
+    pub fn extract_args(&self) -> &'static str {
+        match self.codec {
+            CompressionCodec::Gzip => "-xzf",
+            CompressionCodec::Zstd => "--zstd -xf",
+            CompressionCodec::Xz => "-xJf",
+            CompressionCodec::None => "-xf",
+        }
+    }
+
+    /// Shell check succeeding when target's tar can extract archives of this codec.
+    pub fn preflight_command(&self) -> Option<&'static str> {
+        match self.codec {
+            CompressionCodec::Gzip => Some("command -v gzip >/dev/null"),
+            CompressionCodec::Zstd => {
+                Some("tar --zstd --version >/dev/null 2>&1 && command -v zstd >/dev/null")
+            }
+            CompressionCodec::Xz => {
+                Some("tar --xz --version >/dev/null 2>&1 && command -v xz >/dev/null")
+            }
+            CompressionCodec::None => None,
+        }
+    }
+}
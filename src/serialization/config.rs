@@ -1,14 +1,32 @@
 use std::{collections::HashMap, error::Error, fs::File, io::BufReader, path::Path};
 
-use super::{deploy_package::DeployPackage, deploy_target::DeployTarget};
+use super::{
+    canary::Canary, compression::Compression, deploy_package::DeployPackage,
+    deploy_target::DeployTarget, host_key_policy::HostKeyPolicy, package_filter::PackageFilter,
+    retry_policy::RetryPolicy, rollout_strategy::RolloutStrategy,
+};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Config {
     #[serde(rename = "usesudo")]
     pub use_sudo: Option<bool>,
+    #[serde(rename = "hostKeyPolicy")]
+    pub host_key_policy: Option<HostKeyPolicy>,
+    #[serde(rename = "knownHostsFile")]
+    pub known_hosts_file: Option<String>,
+    pub retry: Option<RetryPolicy>,
+    #[serde(rename = "maxParallel")]
+    pub max_parallel: Option<usize>,
+    pub rollout: Option<RolloutStrategy>,
+    pub canary: Option<Canary>,
+    pub compression: Option<Compression>,
     pub targets: Vec<DeployTarget>,
+    pub groups: Option<HashMap<String, Vec<String>>>,
     pub packages: HashMap<String, DeployPackage>,
+    // hash files on target instead of trusting manifest, set by `--verify`
+    #[serde(skip)]
+    pub verify: bool,
 }
 
 impl Config {
@@ -18,4 +36,197 @@ impl Config {
         let u = serde_json::from_reader(reader)?;
         Ok(u)
     }
+
+    /// Archive codec of the package, package setting wins over config-wide one.
+    pub fn compression(&self, package: &DeployPackage) -> Compression {
+        package.compression.or(self.compression).unwrap_or_default()
+    }
+
+    /// Checks config for problems which can be found without connecting to targets,
+    /// local directories and filters only with `local_files`, when files get packed.
+    pub fn validate(&self, local_files: bool) -> Vec<String> {
+        let mut problems: Vec<String> = Vec::new();
+
+        if self.max_parallel == Some(0) {
+            problems.push("maxParallel should be greater than 0".to_string());
+        }
+        if let Some(batch_size) = self.rollout.as_ref().and_then(|r| r.batch_size.as_ref()) {
+            if !batch_size.is_valid() {
+                problems.push(format!(
+                    "rollout: invalid batchSize {}, expected count or percent like \"25%\"",
+                    batch_size
+                ));
+            }
+        }
+
+        if let Some(problem) = self.compression.and_then(|c| c.validate()) {
+            problems.push(problem);
+        }
+
+        if let Some(canary) = &self.canary {
+            for entry in &canary.targets {
+                let known = self
+                    .targets
+                    .iter()
+                    .any(|target| target.matches_name_or_tag(entry));
+                if !known {
+                    problems.push(format!("canary: {} is not a target or tag", entry));
+                }
+            }
+            if let Some(check) = &canary.health_check {
+                if check.command.is_none() && check.url.is_none() {
+                    problems.push("canary: healthCheck needs command or url".to_string());
+                }
+                if let Some(url) = &check.url {
+                    if !url.starts_with("http://") {
+                        problems.push(format!(
+                            "canary: only http:// urls are supported, got {}",
+                            url
+                        ));
+                    }
+                }
+            }
+        }
+
+        for (index, target) in self.targets.iter().enumerate() {
+            let name = target.name.to_owned().unwrap_or(format!("#{}", index + 1));
+
+            if target.authentication.len() != 1 {
+                problems.push(format!(
+                    "target {}: exactly one authentication method expected",
+                    name
+                ));
+            }
+            for (auth_type, auth_str) in &target.authentication {
+                let creds: Vec<&str> = auth_str.split(':').collect();
+                match auth_type.as_str() {
+                    "password" if creds.len() < 2 => problems.push(format!(
+                        "target {}: password authentication expects \"<user>:<pass>\"",
+                        name
+                    )),
+                    "certificate" if creds.len() < 2 => problems.push(format!(
+                        "target {}: certificate authentication expects \"<user>:<key>[:<pass>]\"",
+                        name
+                    )),
+                    "certificate" if !Path::new(creds[1]).is_file() => {
+                        problems.push(format!("target {}: key {} doesn't exists", name, creds[1]))
+                    }
+                    "password" | "certificate" => {}
+                    _ => problems.push(format!(
+                        "target {}: unknown authentication method {}",
+                        name, auth_type
+                    )),
+                }
+            }
+
+            for package in &target.packages {
+                if !self.packages.contains_key(package) {
+                    problems.push(format!(
+                        "target {}: package {} is not defined",
+                        name, package
+                    ));
+                }
+            }
+
+            // mirror sync can't tell files of packages sharing a directory apart,
+            // directories of nested packages are left out of it instead
+            for (i, package) in target.packages.iter().enumerate() {
+                let Some(package_element) = self.packages.get(package) else {
+                    continue;
+                };
+                for other in &target.packages[i + 1..] {
+                    let Some(other_element) = self.packages.get(other) else {
+                        continue;
+                    };
+                    let same_directory = package_element.target_directory.trim_end_matches('/')
+                        == other_element.target_directory.trim_end_matches('/');
+                    if same_directory && (package_element.is_mirror() || other_element.is_mirror())
+                    {
+                        problems.push(format!(
+                            "target {}: packages {} and {} deploy into the same directory, it can't be mirrored",
+                            name, package, other
+                        ));
+                    }
+                }
+            }
+        }
+
+        let mut package_names: Vec<&String> = self.packages.keys().collect();
+        package_names.sort();
+        for package_name in &package_names {
+            let package = &self.packages[*package_name];
+            if local_files {
+                if !Path::new(&package.local_directory).is_dir() {
+                    problems.push(format!(
+                        "package {}: local directory {} doesn't exists",
+                        package_name, package.local_directory
+                    ));
+                }
+                if let Err(e) = PackageFilter::new(package) {
+                    problems.push(format!(
+                        "package {}: invalid include/exclude: {}",
+                        package_name, e
+                    ));
+                }
+            }
+            if let Err(e) = package.protect_set() {
+                problems.push(format!(
+                    "package {}: invalid protect pattern: {}",
+                    package_name, e
+                ));
+            }
+            if let Some(problem) = package.compression.and_then(|c| c.validate()) {
+                problems.push(format!("package {}: {}", package_name, problem));
+            }
+            if package.releases.is_some() && package.backup.unwrap_or(false) {
+                problems.push(format!(
+                    "package {}: backup can't be combined with releases, previous releases already serve as backup",
+                    package_name
+                ));
+            }
+            if !package.target_directory.starts_with('/') {
+                problems.push(format!(
+                    "package {}: target directory {} should be absolute",
+                    package_name, package.target_directory
+                ));
+            }
+        }
+
+        // packages sharing a backup directory would share its journal and backup ids
+        let mut backup_directories: HashMap<String, &String> = HashMap::new();
+        for package_name in &package_names {
+            let package = &self.packages[*package_name];
+            if package.backup.unwrap_or(false) && package.backup_directory.is_some() {
+                let dir = package.backup_directory(package_name);
+                if let Some(other) = backup_directories.insert(dir.to_string(), package_name) {
+                    problems.push(format!(
+                        "package {}: backup directory {} is already used by package {}",
+                        package_name, dir, other
+                    ));
+                }
+            }
+        }
+
+        if let Some(groups) = &self.groups {
+            let mut group_names: Vec<&String> = groups.keys().collect();
+            group_names.sort();
+            for group_name in group_names {
+                for member in &groups[group_name] {
+                    let known = groups.contains_key(member)
+                        || self
+                            .targets
+                            .iter()
+                            .any(|target| target.matches_name_or_tag(member));
+                    if !known {
+                        problems.push(format!(
+                            "group {}: {} is not a target, tag or group",
+                            group_name, member
+                        ));
+                    }
+                }
+            }
+        }
+
+        problems
+    }
 }
@@ -0,0 +1,31 @@
+use serde::{Deserialize, Serialize};
+
+/// Pre/post deploy action, either a plain command string or an object with options.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum DeployAction {
+    Command(String),
+    Extended {
+        command: String,
+        #[serde(rename = "continueOnError")]
+        continue_on_error: Option<bool>,
+    },
+}
+
+impl DeployAction {
+    pub fn command(&self) -> &str {
+        match self {
+            DeployAction::Command(command) => command,
+            DeployAction::Extended { command, .. } => command,
+        }
+    }
+
+    pub fn continue_on_error(&self) -> bool {
+        match self {
+            DeployAction::Command(_) => false,
+            DeployAction::Extended {
+                continue_on_error, ..
+            } => continue_on_error.unwrap_or(false),
+        }
+    }
+}
@@ -1,5 +1,11 @@
+use crate::core::constants::STATE_DIRECTORY;
+use globset::{Glob, GlobSet, GlobSetBuilder};
 use serde::{Deserialize, Serialize};
 
+use super::{
+    compression::Compression, deploy_action::DeployAction, releases::Releases, sync_mode::SyncMode,
+};
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct DeployPackage {
     #[serde(rename = "localDirectory")]
@@ -7,7 +13,96 @@ pub struct DeployPackage {
     #[serde(rename = "targetDirectory")]
     pub target_directory: String,
     #[serde(rename = "preDeployActions")]
-    pub pre_deploy_actions: Option<Vec<String>>,
+    pub pre_deploy_actions: Option<Vec<DeployAction>>,
     #[serde(rename = "postDeployActions")]
-    pub post_deploy_actions: Option<Vec<String>>,
+    pub post_deploy_actions: Option<Vec<DeployAction>>,
+    // stop remaining packages of the target when this package fails (default: true)
+    #[serde(rename = "abortTargetOnFailure")]
+    pub abort_target_on_failure: Option<bool>,
+    // deploy into `releases/<id>` under target directory and switch `current` symlink
+    pub releases: Option<Releases>,
+    // archive overwritten files before extracting, so deploy can be rolled back
+    pub backup: Option<bool>,
+    #[serde(rename = "backupDirectory")]
+    pub backup_directory: Option<String>,
+    pub sync: Option<SyncMode>,
+    // files never removed in mirror mode, `dir/` protects whole directory
+    pub protect: Option<Vec<String>>,
+    // gitignore-style patterns, `.deployignore` in local directory is applied as well
+    pub include: Option<Vec<String>>,
+    pub exclude: Option<Vec<String>>,
+    // archive codec, overrides config-wide setting
+    pub compression: Option<Compression>,
+}
+
+impl DeployPackage {
+    /// Directory with currently deployed files, `current` release in release mode.
+    pub fn live_directory(&self) -> String {
+        match &self.releases {
+            Some(_) => format!("{}/current/", self.target_directory.trim_end_matches('/')),
+            None => self.target_directory.to_string(),
+        }
+    }
+
+    pub fn is_mirror(&self) -> bool {
+        self.sync.unwrap_or_default() == SyncMode::Mirror
+    }
+
+    /// Compiles protect list, `dir/` entries are treated as `dir/**`.
+    pub fn protect_set(&self) -> Result<GlobSet, globset::Error> {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in self.protect.iter().flatten() {
+            let pattern = match pattern.strip_suffix('/') {
+                Some(dir) => format!("{}/**", dir),
+                None => pattern.to_string(),
+            };
+            builder.add(Glob::new(pattern.trim_start_matches('/'))?);
+        }
+        builder.build()
+    }
+
+    /// Directory on target with deploy state of the package, kept outside of
+    /// target directory so it's never served along with deployed files.
+    /// Packages deploying into the same directory each get their own.
+    pub fn state_directory(&self, name: &str) -> String {
+        format!(
+            "{}/{}/{}",
+            STATE_DIRECTORY,
+            escape_path(&self.target_directory),
+            escape_path(name)
+        )
+    }
+
+    /// Directory with backups and their journal, inside state directory by default.
+    pub fn backup_directory(&self, name: &str) -> String {
+        match &self.backup_directory {
+            Some(dir) => dir.trim_end_matches('/').to_string(),
+            None => format!("{}/backups", self.state_directory(name)),
+        }
+    }
+
+    /// Manifest of deployed files, inside state directory.
+    pub fn manifest_file(&self, name: &str) -> String {
+        format!("{}/manifest.json", self.state_directory(name))
+    }
+}
+
+/// Escapes path into a single file name the way `systemd-escape --path` does,
+/// `/var/www/my-app/` becomes `var-www-my\x2dapp`.
+fn escape_path(path: &str) -> String {
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    if segments.is_empty() {
+        return "-".to_string();
+    }
+
+    let mut escaped = String::new();
+    for byte in segments.join("/").bytes() {
+        match byte {
+            b'/' => escaped.push('-'),
+            b'.' if escaped.is_empty() => escaped.push_str("\\x2e"),
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'_' | b'.' => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("\\x{:02x}", byte)),
+        }
+    }
+    escaped
 }
@@ -2,6 +2,8 @@ use std::collections::HashMap;
 
 use serde::{Deserialize, Serialize};
 
+use super::retry_policy::RetryPolicy;
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct DeployTarget {
     pub name: Option<String>,
@@ -9,4 +11,21 @@ pub struct DeployTarget {
     pub port: u16,
     pub authentication: HashMap<String, String>,
     pub packages: Vec<String>,
+    pub tags: Option<Vec<String>>,
+    #[serde(rename = "hostKey")]
+    pub host_key: Option<String>,
+    #[serde(rename = "hostKeyFingerprint")]
+    pub host_key_fingerprint: Option<String>,
+    pub retry: Option<RetryPolicy>,
+}
+
+impl DeployTarget {
+    /// Whether `entry` is the name of the target or one of its tags.
+    pub fn matches_name_or_tag(&self, entry: &str) -> bool {
+        self.name.as_deref() == Some(entry)
+            || self
+                .tags
+                .as_ref()
+                .is_some_and(|tags| tags.iter().any(|tag| tag == entry))
+    }
 }
@@ -0,0 +1,13 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum HostKeyPolicy {
+    /// only connect to hosts already present in known_hosts (or pinned in config)
+    Strict,
+    /// trust unknown hosts on first use and remember them in known_hosts
+    #[default]
+    AcceptNew,
+    /// skip known_hosts verification entirely (pinned keys are still checked)
+    Off,
+}
@@ -1,3 +1,12 @@
+pub mod canary;
+pub mod compression;
 pub mod config;
+pub mod deploy_action;
 pub mod deploy_package;
 pub mod deploy_target;
+pub mod host_key_policy;
+pub mod package_filter;
+pub mod releases;
+pub mod retry_policy;
+pub mod rollout_strategy;
+pub mod sync_mode;
@@ -0,0 +1,75 @@
+use super::deploy_package::DeployPackage;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::path::Path;
+
+pub static DEPLOY_IGNORE_FILE: &str = ".deployignore";
+pub static HASH_CACHE_FILE: &str = ".deploy-cache";
+
+/// Decides which local files belong to package, using gitignore-style `include`/`exclude`
+/// patterns and `.deployignore` from package root.
+pub struct PackageFilter {
+    include: Option<Gitignore>,
+    exclude: Gitignore,
+}
+
+impl PackageFilter {
+    pub fn new(package: &DeployPackage) -> Result<PackageFilter, ignore::Error> {
+        let root = Path::new(&package.local_directory);
+
+        let mut exclude = GitignoreBuilder::new(root);
+        let deploy_ignore = root.join(DEPLOY_IGNORE_FILE);
+        if deploy_ignore.is_file() {
+            if let Some(e) = exclude.add(&deploy_ignore) {
+                return Err(e);
+            }
+        }
+        for pattern in package.exclude.iter().flatten() {
+            exclude.add_line(None, pattern)?;
+        }
+
+        let include = match &package.include {
+            Some(patterns) if !patterns.is_empty() => {
+                let mut include = GitignoreBuilder::new(root);
+                for pattern in patterns {
+                    include.add_line(None, pattern)?;
+                }
+                Some(include.build()?)
+            }
+            _ => None,
+        };
+
+        Ok(PackageFilter {
+            include,
+            exclude: exclude.build()?,
+        })
+    }
+
+    /// Checks directory path relative to package root, nothing below excluded directory
+    /// can be included again, same as with gitignore.
+    pub fn is_dir_excluded(&self, path: &str) -> bool {
+        self.exclude
+            .matched_path_or_any_parents(path, true)
+            .is_ignore()
+    }
+
+    /// Checks file path relative to package root.
+    pub fn is_included(&self, path: &str) -> bool {
+        // deploy.rs own files, cache may be caught mid-write as `.deploy-cache.tmp`
+        let own_file = path == DEPLOY_IGNORE_FILE
+            || path
+                .strip_prefix(HASH_CACHE_FILE)
+                .is_some_and(|rest| rest.is_empty() || rest == ".tmp");
+        if own_file {
+            return false;
+        }
+        if let Some(include) = &self.include {
+            if !include.matched_path_or_any_parents(path, false).is_ignore() {
+                return false;
+            }
+        }
+        !self
+            .exclude
+            .matched_path_or_any_parents(path, false)
+            .is_ignore()
+    }
+}
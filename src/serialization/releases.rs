@@ -0,0 +1,14 @@
+use crate::core::constants::RELEASES_KEEP;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct Releases {
+    // amount of releases kept on target, including the current one
+    pub keep: Option<usize>,
+}
+
+impl Releases {
+    pub fn keep(&self) -> usize {
+        self.keep.unwrap_or(RELEASES_KEEP).max(1)
+    }
+}
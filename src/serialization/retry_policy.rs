@@ -0,0 +1,52 @@
+use crate::core::constants::{
+    RETRY_BACKOFF_FACTOR, RETRY_INITIAL_DELAY_MS, RETRY_JITTER, RETRY_MAX_ATTEMPTS,
+    RETRY_MAX_DELAY_MS,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, Debug)]
+pub struct RetryPolicy {
+    #[serde(rename = "maxAttempts")]
+    pub max_attempts: Option<u32>,
+    #[serde(rename = "initialDelayMs")]
+    pub initial_delay_ms: Option<u64>,
+    #[serde(rename = "backoffFactor")]
+    pub backoff_factor: Option<f64>,
+    #[serde(rename = "maxDelayMs")]
+    pub max_delay_ms: Option<u64>,
+    // fraction of the delay randomly added or subtracted, 0.0 - 1.0
+    pub jitter: Option<f64>,
+}
+
+impl RetryPolicy {
+    /// Fills fields missing in this policy from `fallback`.
+    pub fn merge(&self, fallback: &RetryPolicy) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.max_attempts.or(fallback.max_attempts),
+            initial_delay_ms: self.initial_delay_ms.or(fallback.initial_delay_ms),
+            backoff_factor: self.backoff_factor.or(fallback.backoff_factor),
+            max_delay_ms: self.max_delay_ms.or(fallback.max_delay_ms),
+            jitter: self.jitter.or(fallback.jitter),
+        }
+    }
+
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts.unwrap_or(RETRY_MAX_ATTEMPTS).max(1)
+    }
+
+    pub fn initial_delay_ms(&self) -> u64 {
+        self.initial_delay_ms.unwrap_or(RETRY_INITIAL_DELAY_MS)
+    }
+
+    pub fn backoff_factor(&self) -> f64 {
+        self.backoff_factor.unwrap_or(RETRY_BACKOFF_FACTOR).max(1.0)
+    }
+
+    pub fn max_delay_ms(&self) -> u64 {
+        self.max_delay_ms.unwrap_or(RETRY_MAX_DELAY_MS)
+    }
+
+    pub fn jitter(&self) -> f64 {
+        self.jitter.unwrap_or(RETRY_JITTER).clamp(0.0, 1.0)
+    }
+}
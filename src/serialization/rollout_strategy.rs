@@ -0,0 +1,74 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum BatchSize {
+    // fixed amount of targets per wave
+    Count(usize),
+    // share of all targets per wave, e.g. "25%"
+    Percent(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct RolloutStrategy {
+    #[serde(rename = "batchSize")]
+    pub batch_size: Option<BatchSize>,
+    #[serde(rename = "haltOnFailure")]
+    pub halt_on_failure: Option<bool>,
+}
+
+impl BatchSize {
+    fn percent(&self) -> Option<f64> {
+        match self {
+            BatchSize::Count(_) => None,
+            BatchSize::Percent(value) => value
+                .trim()
+                .strip_suffix('%')
+                .and_then(|value| value.trim().parse::<f64>().ok())
+                .filter(|value| *value > 0.0 && *value <= 100.0),
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        match self {
+            BatchSize::Count(count) => *count > 0,
+            BatchSize::Percent(_) => self.percent().is_some(),
+        }
+    }
+
+    /// Amount of targets deployed together, at least one.
+    pub fn wave_size(&self, targets_count: usize) -> usize {
+        let size = match self {
+            BatchSize::Count(count) => *count,
+            BatchSize::Percent(_) => {
+                let percent = self.percent().unwrap_or(100.0);
+                (targets_count as f64 * percent / 100.0).ceil() as usize
+            }
+        };
+        size.max(1)
+    }
+}
+
+impl fmt::Display for BatchSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BatchSize::Count(count) => write!(f, "{}", count),
+            BatchSize::Percent(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+impl RolloutStrategy {
+    /// Without batch size all targets are deployed in a single wave.
+    pub fn wave_size(&self, targets_count: usize) -> usize {
+        match &self.batch_size {
+            Some(batch_size) => batch_size.wave_size(targets_count),
+            None => targets_count.max(1),
+        }
+    }
+
+    pub fn halt_on_failure(&self) -> bool {
+        self.halt_on_failure.unwrap_or(true)
+    }
+}
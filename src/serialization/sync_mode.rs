@@ -0,0 +1,11 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum SyncMode {
+    // upload new and changed files, keep everything else on target
+    #[default]
+    Update,
+    // additionally remove files which no longer exist locally
+    Mirror,
+}
@@ -1,3 +1,4 @@
 pub trait BaseState<T> {
+    #[allow(clippy::new_ret_no_self)]
     fn new() -> T;
 }
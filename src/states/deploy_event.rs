@@ -0,0 +1,93 @@
+use serde::Serialize;
+
+/// Deployment progress event, printed as one JSON line per event with `--output json`.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum DeployEvent {
+    CanaryStarted {
+        targets: Vec<String>,
+    },
+    CanarySoak {
+        seconds: u64,
+    },
+    HealthCheckFinished {
+        index: u32,
+        target: String,
+        passed: bool,
+        error: Option<String>,
+    },
+    WaveStarted {
+        wave: usize,
+        waves: usize,
+        targets: Vec<String>,
+    },
+    TargetStarted {
+        index: u32,
+        target: String,
+    },
+    ChecksumStarted {
+        index: u32,
+        target: String,
+        package: String,
+    },
+    ChecksumProgress {
+        index: u32,
+        target: String,
+        package: String,
+        files: u64,
+        files_len: u64,
+        bytes: u64,
+        bytes_len: u64,
+    },
+    ChecksumComputed {
+        index: u32,
+        target: String,
+        package: String,
+        files: usize,
+    },
+    UploadProgress {
+        index: u32,
+        target: String,
+        package: String,
+        position: u64,
+        length: u64,
+    },
+    NoChanges {
+        index: u32,
+        target: String,
+        package: String,
+    },
+    PackageFinishing {
+        index: u32,
+        target: String,
+        package: String,
+    },
+    ActionStarted {
+        index: u32,
+        target: String,
+        package: String,
+        command: String,
+    },
+    ActionFinished {
+        index: u32,
+        target: String,
+        package: String,
+        command: String,
+        exit_code: Option<u32>,
+    },
+    TargetFinished {
+        index: u32,
+        target: String,
+    },
+    TargetFailed {
+        index: u32,
+        target: String,
+        step: String,
+        package: String,
+        error: String,
+    },
+    DeploymentFinished {
+        succeeded: u32,
+        failed: u32,
+    },
+}
@@ -1,2 +1,3 @@
 pub mod base_state;
+pub mod deploy_event;
 pub mod ui_state;
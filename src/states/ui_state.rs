@@ -2,7 +2,7 @@ use std::collections::BTreeMap;
 
 use ratatui::widgets::ScrollbarState;
 
-use super::base_state::BaseState;
+use super::{base_state::BaseState, deploy_event::DeployEvent};
 
 #[allow(non_camel_case_types)]
 #[derive(Copy, Clone)]
@@ -22,6 +22,20 @@ pub enum UITargetState {
     TARGET_NO_CHANGES,
     TARGET_FINISHING,
     TARGET_FINISHED,
+    TARGET_FAILED,
+}
+
+impl UITargetState {
+    pub fn step_name(&self) -> &'static str {
+        match self {
+            UITargetState::TARGET_START => "start",
+            UITargetState::TARGET_CHECKSUM => "checksum",
+            UITargetState::TARGET_UPLOADING | UITargetState::TARGET_NO_CHANGES => "upload",
+            UITargetState::TARGET_FINISHING => "finishing",
+            UITargetState::TARGET_FINISHED => "finished",
+            UITargetState::TARGET_FAILED => "failed",
+        }
+    }
 }
 
 pub struct TargetState {
@@ -30,6 +44,12 @@ pub struct TargetState {
     pub upload_package: String,
     pub upload_pos: u64,
     pub upload_len: u64,
+    pub checksum_files: u64,
+    pub checksum_files_len: u64,
+    pub checksum_bytes: u64,
+    pub checksum_bytes_len: u64,
+    pub error: Option<String>,
+    pub failed_at: Option<UITargetState>,
 }
 
 pub struct UIStore {
@@ -37,8 +57,10 @@ pub struct UIStore {
     pub targets_count: u32,
     pub packages_count: u32,
     pub deployed_count: u32,
+    pub failed_count: u32,
     pub deployment_targets: BTreeMap<u32, TargetState>,
     pub exit_on_finish: bool,
+    pub json_output: bool,
 
     // system
     pub vertical_scroll: u16,
@@ -53,8 +75,10 @@ impl BaseState<UIStore> for UIStore {
             targets_count: 0,
             packages_count: 0,
             deployed_count: 0, // successfully deployed targets
+            failed_count: 0,
             deployment_targets: BTreeMap::new(),
             exit_on_finish: false,
+            json_output: false,
             vertical_scroll: 0,
             vertical_scroll_max: 0,
             vertical_scroll_state: ScrollbarState::default(),
@@ -83,6 +107,11 @@ impl UIStore {
         self
     }
 
+    pub fn set_failed_count(&mut self, count: u32) -> &mut UIStore {
+        self.failed_count = count;
+        self
+    }
+
     pub fn set_deployment_target(&mut self, index: u32, target: TargetState) -> &mut UIStore {
         self.deployment_targets.insert(index, target);
         self
@@ -93,14 +122,176 @@ impl UIStore {
         self
     }
 
+    pub fn set_json_output(&mut self, state: bool) -> &mut UIStore {
+        self.json_output = state;
+        self
+    }
+
+    pub fn update_target_state(
+        &mut self,
+        index: u32,
+        state: UITargetState,
+        package: Option<&str>,
+    ) -> &mut UIStore {
+        let Some(target) = self.deployment_targets.get_mut(&index) else {
+            return self;
+        };
+        target.state = state;
+        if let Some(package) = package {
+            target.upload_package = package.to_string();
+        }
+
+        let name = target.name.to_string();
+        let package = target.upload_package.to_string();
+        match state {
+            UITargetState::TARGET_START => self.emit(DeployEvent::TargetStarted {
+                index,
+                target: name,
+            }),
+            UITargetState::TARGET_CHECKSUM if !package.is_empty() => {
+                self.emit(DeployEvent::ChecksumStarted {
+                    index,
+                    target: name,
+                    package,
+                })
+            }
+            UITargetState::TARGET_NO_CHANGES => self.emit(DeployEvent::NoChanges {
+                index,
+                target: name,
+                package,
+            }),
+            UITargetState::TARGET_FINISHING => self.emit(DeployEvent::PackageFinishing {
+                index,
+                target: name,
+                package,
+            }),
+            UITargetState::TARGET_FINISHED => self.emit(DeployEvent::TargetFinished {
+                index,
+                target: name,
+            }),
+            _ => {}
+        }
+        self
+    }
+
+    pub fn update_upload_progress(
+        &mut self,
+        index: u32,
+        package: &str,
+        upload_pos: u64,
+        upload_len: u64,
+    ) -> &mut UIStore {
+        let Some(target) = self.deployment_targets.get_mut(&index) else {
+            return self;
+        };
+
+        // only report whole percents, chunks are too small to print each one
+        let percent = |pos: u64| pos * 100 / upload_len.max(1);
+        let report = !matches!(target.state, UITargetState::TARGET_UPLOADING)
+            || target.upload_package != package
+            || percent(target.upload_pos) != percent(upload_pos)
+            || upload_pos == upload_len;
+
+        target.state = UITargetState::TARGET_UPLOADING;
+        target.upload_package = package.to_string();
+        target.upload_pos = upload_pos;
+        target.upload_len = upload_len;
+
+        if report {
+            let name = target.name.to_string();
+            self.emit(DeployEvent::UploadProgress {
+                index,
+                target: name,
+                package: package.to_string(),
+                position: upload_pos,
+                length: upload_len,
+            });
+        }
+        self
+    }
+
+    pub fn update_checksum_progress(
+        &mut self,
+        index: u32,
+        package: &str,
+        files: (u64, u64),
+        bytes: (u64, u64),
+    ) -> &mut UIStore {
+        let Some(target) = self.deployment_targets.get_mut(&index) else {
+            return self;
+        };
+
+        // same as upload, only whole percents are reported
+        let percent = |pos: u64| pos * 100 / bytes.1.max(1);
+        let report = target.upload_package != package
+            || target.checksum_bytes_len != bytes.1
+            || percent(target.checksum_bytes) != percent(bytes.0)
+            || files.0 == files.1 && target.checksum_files != files.0;
+
+        target.upload_package = package.to_string();
+        (target.checksum_files, target.checksum_files_len) = files;
+        (target.checksum_bytes, target.checksum_bytes_len) = bytes;
+
+        if report {
+            let name = target.name.to_string();
+            self.emit(DeployEvent::ChecksumProgress {
+                index,
+                target: name,
+                package: package.to_string(),
+                files: files.0,
+                files_len: files.1,
+                bytes: bytes.0,
+                bytes_len: bytes.1,
+            });
+        }
+        self
+    }
+
+    pub fn fail_target(&mut self, index: u32, error: String) -> &mut UIStore {
+        let Some(target) = self.deployment_targets.get_mut(&index) else {
+            return self;
+        };
+        if !matches!(target.state, UITargetState::TARGET_FAILED) {
+            target.failed_at = Some(target.state);
+        }
+        target.state = UITargetState::TARGET_FAILED;
+        target.error = Some(error.to_string());
+
+        let event = DeployEvent::TargetFailed {
+            index,
+            target: target.name.to_string(),
+            step: target
+                .failed_at
+                .map(|state| state.step_name())
+                .unwrap_or("start")
+                .to_string(),
+            package: target.upload_package.to_string(),
+            error,
+        };
+        self.emit(event);
+        self
+    }
+
+    /// Prints event as a JSON line when json output is enabled.
+    pub fn emit(&self, event: DeployEvent) {
+        if !self.json_output {
+            return;
+        }
+        if let Ok(line) = serde_json::to_string(&event) {
+            println!("{}", line);
+        }
+    }
+
     pub fn finalize(&self) -> UIStore {
         UIStore {
             screen: self.screen,
             targets_count: self.targets_count,
             packages_count: self.packages_count,
             deployed_count: self.deployed_count,
+            failed_count: self.failed_count,
             deployment_targets: BTreeMap::new(),
             exit_on_finish: self.exit_on_finish,
+            json_output: self.json_output,
             vertical_scroll: 0,
             vertical_scroll_max: 0,
             vertical_scroll_state: self.vertical_scroll_state,
@@ -1,6 +1,6 @@
 use crate::{
     core::constants::VERSION,
-    states::ui_state::{UIScreen, UIStore, UITargetState},
+    states::ui_state::{TargetState, UIScreen, UIStore, UITargetState},
 };
 use futures::lock::Mutex;
 use ratatui::{
@@ -11,14 +11,18 @@ use ratatui::{
 };
 use std::sync::Arc;
 
-pub fn convert_target_state_to_str(
-    state: UITargetState,
-    upload_package: String,
-    upload_pos: u64,
-    upload_len: u64,
-) -> Result<String, ()> {
-    Ok(match state {
+pub fn convert_target_state_to_str(target: &TargetState) -> String {
+    let upload_package = &target.upload_package;
+    let upload_pos = target.upload_pos;
+    let upload_len = target.upload_len;
+    match target.state {
         UITargetState::TARGET_START => "[1/5] starting deployment".to_string(),
+        UITargetState::TARGET_CHECKSUM if target.checksum_files_len > 0 => {
+            format!(
+                "[2/5] computing checksum {} ({}/{} files)",
+                upload_package, target.checksum_files, target.checksum_files_len
+            )
+        }
         UITargetState::TARGET_CHECKSUM => {
             format!("[2/5] computing checksum {}", upload_package)
         }
@@ -33,7 +37,22 @@ pub fn convert_target_state_to_str(
         }
         UITargetState::TARGET_FINISHING => "[4/5] finishing deployment".to_string(),
         UITargetState::TARGET_FINISHED => "[5/5] finished".to_string(),
-    })
+        UITargetState::TARGET_FAILED => {
+            let step = match target.failed_at {
+                Some(UITargetState::TARGET_START) | None => "[1/5] failed starting deployment",
+                Some(UITargetState::TARGET_CHECKSUM) => "[2/5] failed computing checksum",
+                Some(UITargetState::TARGET_UPLOADING) | Some(UITargetState::TARGET_NO_CHANGES) => {
+                    "[3/5] failed uploading"
+                }
+                Some(_) => "[4/5] failed finishing deployment",
+            };
+            format!(
+                "{}: {}",
+                format!("{} {}", step, upload_package).trim_end(),
+                target.error.as_deref().unwrap_or("unknown error")
+            )
+        }
+    }
 }
 
 pub async fn render_ui<'a>(frame: &mut Frame<'a>, ui_state: Arc<Mutex<UIStore>>) -> () {
@@ -45,20 +64,27 @@ pub async fn render_ui<'a>(frame: &mut Frame<'a>, ui_state: Arc<Mutex<UIStore>>)
         .split(frame.area());
 
     let paragraph = format!("DEPLOY.RS {}", VERSION);
-    let mut state = "State: loading config";
+    let mut state = String::from("State: loading config");
     let mut state_color = Color::LightYellow;
     match ui_read.screen {
         UIScreen::TARGET_START => {
-            state = "State: starting deployment";
+            state = String::from("State: starting deployment");
         }
         UIScreen::FINISHED | UIScreen::FINISHED_END => {
-            state = "State: deployment finished";
-            state_color = Color::LightGreen;
+            state = format!(
+                "State: deployment finished, {} succeeded, {} failed",
+                ui_read.deployed_count, ui_read.failed_count
+            );
+            state_color = if ui_read.failed_count > 0 {
+                Color::LightRed
+            } else {
+                Color::LightGreen
+            };
         }
         _ => {}
     }
 
-    let motd = Paragraph::new(String::from(state))
+    let motd = Paragraph::new(state)
         .block(
             Block::default()
                 .borders(Borders::ALL)
@@ -110,9 +136,9 @@ pub async fn render_ui<'a>(frame: &mut Frame<'a>, ui_state: Arc<Mutex<UIStore>>)
 
     // calculate column widths
     for render_entry in ui_read.deployment_targets.iter() {
-        el_index = el_index + 1;
+        el_index += 1;
         //println!("{}", render_entry.0);
-        if render_entry.0.clone() < start_from {
+        if *render_entry.0 < start_from {
             //&start_from {
             continue;
         }
@@ -124,13 +150,7 @@ pub async fn render_ui<'a>(frame: &mut Frame<'a>, ui_state: Arc<Mutex<UIStore>>)
         let target_name = format!("{}. {}", el_index, render_entry.1.name);
 
         // state label
-        let state_str = convert_target_state_to_str(
-            render_entry.1.state, //UITargetState::TARGET_FINISHING,
-            render_entry.1.upload_package.to_string(),
-            render_entry.1.upload_pos,
-            render_entry.1.upload_len,
-        )
-        .unwrap();
+        let state_str = convert_target_state_to_str(render_entry.1);
 
         let f_len = target_name.len() + spacing;
         let s_len = state_str.len() + spacing;
@@ -143,16 +163,16 @@ pub async fn render_ui<'a>(frame: &mut Frame<'a>, ui_state: Arc<Mutex<UIStore>>)
             second_el_width = s_len;
         }
 
-        render_index = render_index + 1;
+        render_index += 1;
     }
 
     let mut render_index = 0;
     let mut el_index = 0;
     for render_entry in ui_read.deployment_targets.iter() {
-        el_index = el_index + 1;
+        el_index += 1;
         let y_offset = y_area_margin * 2 + render_index * (element_height + y_area_margin);
         //println!("{}", render_entry.0);
-        if render_entry.0.clone() < start_from {
+        if *render_entry.0 < start_from {
             //&start_from {
             continue;
         }
@@ -185,19 +205,16 @@ pub async fn render_ui<'a>(frame: &mut Frame<'a>, ui_state: Arc<Mutex<UIStore>>)
         );
 
         // state label
-        element_index = element_index + 1;
+        element_index += 1;
         let element_width: u16 = second_el_width as u16;
-        let state_str = convert_target_state_to_str(
-            render_entry.1.state,
-            render_entry.1.upload_package.to_string(),
-            render_entry.1.upload_pos,
-            render_entry.1.upload_len,
-        )
-        .unwrap();
+        let state_str = convert_target_state_to_str(render_entry.1);
         let mut state_label = Paragraph::new(state_str).gray();
         if matches!(render_entry.1.state, UITargetState::TARGET_FINISHED) {
             state_label = state_label.light_green();
         }
+        if matches!(render_entry.1.state, UITargetState::TARGET_FAILED) {
+            state_label = state_label.light_red();
+        }
 
         frame.render_widget(
             state_label,
@@ -211,10 +228,20 @@ pub async fn render_ui<'a>(frame: &mut Frame<'a>, ui_state: Arc<Mutex<UIStore>>)
             ),
         );
 
-        // progress bar for uploading
-        if matches!(render_entry.1.state, UITargetState::TARGET_UPLOADING) {
-            element_index = element_index + 1;
-            let percent = render_entry.1.upload_pos as f64 / render_entry.1.upload_len as f64;
+        // progress bar for hashing and uploading
+        let progress = match render_entry.1.state {
+            UITargetState::TARGET_UPLOADING => {
+                Some((render_entry.1.upload_pos, render_entry.1.upload_len))
+            }
+            UITargetState::TARGET_CHECKSUM if render_entry.1.checksum_files_len > 0 => Some((
+                render_entry.1.checksum_bytes,
+                render_entry.1.checksum_bytes_len,
+            )),
+            _ => None,
+        };
+        if let Some((pos, len)) = progress {
+            element_index += 1;
+            let percent = pos as f64 / len.max(1) as f64;
             let gauge = Gauge::default()
                 .block(Block::default())
                 .gauge_style(Style::default().fg(Color::LightYellow).bg(Color::DarkGray))
@@ -234,6 +261,6 @@ pub async fn render_ui<'a>(frame: &mut Frame<'a>, ui_state: Arc<Mutex<UIStore>>)
             );
         }
 
-        render_index = render_index + 1;
+        render_index += 1;
     }
 }